@@ -0,0 +1,46 @@
+//! Compare plain interpretation of the day 19 part 1 program with the
+//! compiled closures.
+//!
+//! Run with `cargo bench --bench device`.
+
+use std::time::{Duration, Instant};
+
+use aoc2018::{device::Assembler, load};
+
+const RUNS: u32 = 5;
+
+fn main() {
+    let s = load(19);
+
+    // neither timing includes parsing the program
+    let mut assembler = Assembler::load(&s).unwrap();
+    let interpreted = measure(|| {
+        assembler.regs = [0; 6];
+        assembler.interpret(u64::MAX);
+        assembler.regs[0]
+    });
+
+    let compiled = assembler.compile();
+    let threaded = measure(|| {
+        let mut regs = [0; 6];
+        compiled.exec(&mut regs, u64::MAX);
+        regs[0]
+    });
+
+    println!("interpret: {:?}", interpreted);
+    println!("compiled:  {:?}", threaded);
+    println!(
+        "speedup:   {:.2}x",
+        interpreted.as_secs_f64() / threaded.as_secs_f64()
+    );
+}
+
+/// average time of a few runs, checking that they all agree
+fn measure<F: FnMut() -> usize>(mut f: F) -> Duration {
+    let expect = f();
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        assert_eq!(f(), expect);
+    }
+    start.elapsed() / RUNS
+}
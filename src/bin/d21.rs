@@ -1,122 +1,82 @@
 ///! 16777215 = 0xffffff
-use lazy_static::lazy_static;
-use log::debug;
-use std::{
-    collections::{HashMap, HashSet},
-    fmt::{self, Display},
-    str::FromStr,
-};
-
-use aoc2018::{load, setup_logger, AoCError, Result};
-
-// used for Display
-lazy_static! {
-    // name to index map
-    static ref MAP: HashMap<String, usize> = {
-        let names = "addr, addi, mulr, muli, banr, bani, borr, bori, seti, setr, gtir, gtri, gtrr, eqir, eqri, eqrr";
-        let mut map = HashMap::new();
-
-        for (index, name) in names.split(", ").enumerate() {
-            map.insert(name.to_string(), index);
-        }
-
-        map
-    };
-    // index to name map
-    static ref REV: HashMap<usize, String> = {
-        let names = "addr, addi, mulr, muli, banr, bani, borr, bori, seti, setr, gtir, gtri, gtrr, eqir, eqri, eqrr";
-        let mut map = HashMap::new();
-
-        for (index, name) in names.split(", ").enumerate() {
-            map.insert(index, name.to_string());
-        }
-
-        map
-    };
-}
-
-const OPS: [OP; 16] = [
-    addr, addi, mulr, muli, banr, bani, borr, bori, seti, setr, gtir, gtri, gtrr, eqir, eqri, eqrr,
-];
+use std::collections::HashSet;
 
-type OP = fn(&[usize], &mut [usize]);
+use aoc2018::{
+    device::{modes, Assembler, Idiom, Instruction, Mode, Operand, Outcome, REV},
+    flag, load, setup_logger, Result,
+};
+use z3::ast::{Ast, Bool, BV};
 
 fn main() -> Result<()> {
     setup_logger().unwrap();
     let s = load(21);
+    if flag("--transpile") {
+        print!("{}", Assembler::load(&s)?.transpile("d21")?);
+        return Ok(());
+    }
 
-    part1(&s);
-    part2();
+    let first = part1(&s);
+    let last = part2();
+    if flag("--z3") {
+        match solve(&Assembler::load(&s)?, FORKS, LIMIT) {
+            Some(answers) => println!(
+                "z3: part1 {}, part2 {}, {}",
+                answers.0,
+                answers.1,
+                if Some(answers) == first.zip(last) {
+                    "agrees"
+                } else {
+                    "differs"
+                }
+            ),
+            None => println!("z3: no R0 halts within {} forks", FORKS),
+        }
+    }
 
     Ok(())
 }
 
-fn part1(s: &str) {
-    let mut assembler = Assembler::load(s).unwrap();
-    assembler.exec(2000);
-    println!(
-        "part1: read the log and find the value of R5 when excuting eqrr 5 0 1 or R1 = R5 == R0"
-    );
+/// pass `--symbolic` to print every way the program can end
+fn symbolic() -> bool {
+    flag("--symbolic")
 }
 
-fn part2() {
-    fast(200000);
-}
-
-#[derive(Debug, Default)]
-struct Assembler {
-    instructions: Vec<Instruction>,
-    origin: Vec<String>, // for debug
-    regs: [usize; 6],
-    ip: usize,
-}
+fn part1(s: &str) -> Option<u64> {
+    let mut assembler = Assembler::load(s).unwrap();
+    if flag("--profile") {
+        assembler.enable_profiling();
+    }
+    // the log shows the value of R5 when excuting eqrr 5 0 1 or R1 = R5 == R0
+    assembler.exec(2000);
 
-impl Assembler {
-    fn load(s: &str) -> Result<Self> {
-        let mut assembler = Assembler::default();
-        for origin in s.lines() {
-            let instruction = origin.parse()?;
-            match instruction {
-                Instruction::IP(reg) => assembler.ip = reg,
-                i => {
-                    assembler.instructions.push(i);
-
-                    // debug setting
-                    assembler.origin.push(origin.to_string())
-                }
-            }
+    // the first R0 that makes the program halt is the one that halts soonest
+    let paths = Assembler::load(s).unwrap().explore(&[0], 100_000_000);
+    if symbolic() {
+        for path in paths.iter() {
+            print!("{}", path);
         }
-
-        Ok(assembler)
     }
+    let answer = paths
+        .iter()
+        .find(|path| matches!(path.outcome, Outcome::Halt { .. }))
+        .and_then(|first| first.fixed.get(&0))
+        .map(|&r0| r0 as u64);
+    match answer {
+        Some(answer) => println!("part1: {}", answer),
+        None => println!("part1: no single R0 found to halt the program"),
+    }
+    if let Some(report) = assembler.profile_report(10) {
+        println!("{}", report);
+    }
+    answer
+}
 
-    fn exec(&mut self, mut limit: u64) {
-        let ip = self.ip;
-        // let mut count = 0;
-        while let Some(instruction) = self.instructions.get(self.regs[ip]) {
-            match instruction {
-                Instruction::OPCode(i) => {
-                    OPS.get(i[0]).unwrap()(i, &mut self.regs);
-                    debug!(
-                        "\n{}[{}, {}, {}, {}, {}, {}]",
-                        instruction,
-                        self.regs[0],
-                        self.regs[1],
-                        self.regs[2],
-                        self.regs[3],
-                        self.regs[4],
-                        self.regs[5]
-                    );
-                }
-                _ => unreachable!(),
-            }
-            self.regs[ip] += 1;
-            limit -= 1;
-            if limit == 0 {
-                break;
-            }
-        }
+fn part2() -> Option<u64> {
+    let answer = fast(200000);
+    if let Some(answer) = answer {
+        println!("part2: {}", answer);
     }
+    answer
 }
 
 /// A modified version to perform input assemble excution
@@ -125,7 +85,7 @@ impl Assembler {
 ///
 /// This function is used to qucikly find the cycle and return the last
 /// element in the cycle as the answer.
-fn fast(limit: usize) {
+fn fast(limit: usize) -> Option<u64> {
     let r0 = 0;
     let mut set = HashSet::new();
     let mut prev = 0;
@@ -141,18 +101,17 @@ fn fast(limit: usize) {
             r5 = compute_r5(r5);
             if 256 > r3 {
                 if r5 == r0 {
-                    return;
+                    return None;
                 } else {
                     count += 1;
                     if set.contains(&r5) {
-                        println!("part2: {}", prev);
-                        return;
+                        return Some(prev);
                     } else {
                         prev = r5;
                         set.insert(r5);
                     }
                     if count > limit {
-                        return;
+                        return None;
                     }
                     continue 'outer;
                 }
@@ -172,207 +131,294 @@ fn compute_r5(mut r5: u64) -> u64 {
     r5
 }
 
-#[derive(Debug)]
-enum Instruction {
-    OPCode([usize; 4]),
-    IP(usize),
-}
+/// Forks on R0 explored by z3, comfortably more than the number of values
+/// R5 takes before it repeats, since every one is a comparison with R5.
+const FORKS: usize = 12_000;
 
-impl Display for Instruction {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Instruction::OPCode(i) => {
-                if let Some(name) = REV.get(&i[0]) {
-                    let start = &name[0..2];
-                    let end = &name[2..];
-                    match end {
-                        "rr" => match start {
-                            "gt" => return writeln!(f, "R{} = R{} > R{}", i[3], i[1], i[2]),
-
-                            "eq" => return writeln!(f, "R{} = R{} == R{}", i[3], i[1], i[2]),
-                            _ => {}
-                        },
-                        "ir" => match start {
-                            "gt" => return writeln!(f, "R{} = {} > R{}", i[3], i[1], i[2]),
-
-                            "eq" => return writeln!(f, "R{} = {} == R{}", i[3], i[1], i[2]),
-                            _ => {}
-                        },
-                        "ri" => match start {
-                            "gt" => return writeln!(f, "R{} = R{} > {}", i[3], i[1], i[2]),
-
-                            "eq" => return writeln!(f, "R{} = R{} == {}", i[3], i[1], i[2]),
-                            _ => {}
-                        },
-                        _ => {}
-                    }
+/// Instructions a path may run without forking before z3 gives up on it.
+const LIMIT: u64 = 1_000_000;
 
-                    let start = &name[0..3];
-                    let end = &name[3..];
-                    match end {
-                        "r" => {
-                            let op = match start {
-                                "add" => '+',
-                                "mul" => '*',
-                                "ban" => '&',
-                                "bor" => '|',
-                                "set" => return writeln!(f, "R{} = R{}", i[3], i[1]),
-                                _ => unreachable!(),
-                            };
-
-                            if i[3] == i[1] {
-                                return writeln!(f, "R{} {}= R{}", i[3], op, i[2]);
-                            } else if i[3] == i[2] {
-                                return writeln!(f, "R{} {}= R{}", i[3], op, i[1]);
-                            } else {
-                                return writeln!(f, "R{} = R{} {} R{}", i[3], i[1], op, i[2]);
-                            }
-                        }
-                        "i" => {
-                            let op = match start {
-                                "add" => '+',
-                                "mul" => '*',
-                                "ban" => '&',
-                                "bor" => '|',
-                                "set" => return writeln!(f, "R{} = {}", i[3], i[1]),
-                                _ => unreachable!(),
-                            };
-
-                            if i[3] == i[1] {
-                                return writeln!(f, "R{} {}= {}", i[3], op, i[2]);
-                            } else if i[3] == i[2] {
-                                return writeln!(f, "R{} {}= {}", i[3], op, i[1]);
-                            } else {
-                                return writeln!(f, "R{} = R{} {} {}", i[3], i[1], op, i[2]);
-                            }
-                        }
-                        _ => {}
+/// Cross-check both parts with z3, pass `--z3` to run it.
+///
+/// The program runs with every register a bit-vector term over the unknown
+/// R0, each instruction encoded from its opcode and operands. Where the next
+/// instruction depends on R0, the path forks on every value z3 finds for
+/// it, up to `forks` forks and `limit` instructions between them. A loop
+/// that `Idiom::detect` finds to divide by counting up is encoded as a
+/// single `bvudiv` of the same registers, since unrolling it would take
+/// billions of terms.
+///
+/// Every path that leaves the program has a condition on R0 and a number of
+/// steps. The R0 that halts soonest satisfies the condition of the shortest
+/// satisfiable path and the R0 that halts last that of the longest, and
+/// both are found by bisection over the paths sorted by their steps.
+fn solve(assembler: &Assembler, forks: usize, limit: u64) -> Option<(u64, u64)> {
+    let cfg = z3::Config::new();
+    let ctx = z3::Context::new(&cfg);
+    let solver = z3::Solver::new(&ctx);
+    // finds where a fork can jump, without the conditions of the paths
+    let scratch = z3::Solver::new(&ctx);
+    let bv = |v: u64| BV::from_u64(&ctx, v, 64);
+    let (zero, one) = (bv(0), bv(1));
+
+    let ip = assembler.ip;
+    let program = &assembler.instructions;
+    let idioms = (0..program.len())
+        .map(|pc| Idiom::detect(program, ip, pc))
+        .collect::<Vec<_>>();
+
+    let r0 = BV::new_const(&ctx, "r0", 64);
+    let mut regs = assembler
+        .regs
+        .iter()
+        .map(|&v| bv(v as u64))
+        .collect::<Vec<_>>();
+    regs[0] = r0.clone();
+
+    // paths still to run, and the conditions and steps of those that halt
+    let mut paths = vec![(assembler.regs[ip], regs, Bool::from_bool(&ctx, true), 0)];
+    let mut halts = vec![];
+    let mut forks = forks;
+    while let Some((mut pc, mut regs, condition, mut steps)) = paths.pop() {
+        let mut budget = limit;
+        loop {
+            let i = match program.get(pc) {
+                Some(Instruction::OPCode(i)) => i,
+                Some(Instruction::IP(_)) => unreachable!(),
+                None => {
+                    halts.push((steps, condition));
+                    break;
+                }
+            };
+            if budget == 0 {
+                break;
+            }
+            budget -= 1;
+
+            if let Some(Idiom::Division { q, d, n, t, exit }) = idioms[pc] {
+                let d = match d {
+                    Operand::Reg(r) => regs[r].clone(),
+                    Operand::Imm(v) => bv(v as u64),
+                };
+                let quotient = regs[n].bvudiv(&d);
+                let q1 = regs[q].bvugt(&quotient).ite(&regs[q], &quotient).simplify();
+                if let (Some(d), Some(q0), Some(q1)) =
+                    (d.simplify().as_u64(), regs[q].as_u64(), q1.as_u64())
+                {
+                    if d > 0 {
+                        // 7 steps for every increment of q, 5 for the final test
+                        steps += 7 * (q1 - q0) + 5;
+                        regs[q] = bv(q1);
+                        regs[t] = one.clone();
+                        pc = exit;
+                        continue;
                     }
                 }
             }
-            Instruction::IP(_) => unreachable!(),
-        }
-        Err(fmt::Error)
-    }
-}
-
-impl FromStr for Instruction {
-    type Err = AoCError;
 
-    fn from_str(s: &str) -> Result<Self> {
-        if s.starts_with("#ip") {
-            let reg = s.trim_start_matches("#ip ");
-            Ok(Instruction::IP(reg.parse()?))
-        } else {
-            let opcode = s.split(' ');
-            let mut buf = vec![];
-            for elem in opcode {
-                buf.push(elem);
+            regs[ip] = bv(pc as u64);
+            let name = REV.get(&i[0])?;
+            let (ma, mb) = modes(name);
+            let operand = |mode: Mode, v: usize| match mode {
+                Mode::Reg => regs.get(v).cloned(),
+                Mode::Imm => Some(bv(v as u64)),
+                Mode::Ignored => Some(zero.clone()),
+            };
+            let (a, b) = (operand(ma, i[1])?, operand(mb, i[2])?);
+            let value = match &name[..2] {
+                "ad" => lift(&a, &b, &|a, b| a.bvadd(b)),
+                "mu" => lift(&a, &b, &|a, b| a.bvmul(b)),
+                "ba" => lift(&a, &b, &|a, b| a.bvand(b)),
+                "bo" => lift(&a, &b, &|a, b| a.bvor(b)),
+                "se" => a,
+                "gt" => lift(&a, &b, &|a, b| a.bvugt(b).ite(&one, &zero)),
+                "eq" => lift(&a, &b, &|a, b| a._eq(b).ite(&one, &zero)),
+                _ => return None,
+            };
+            *regs.get_mut(i[3])? = value;
+            steps += 1;
+
+            let next = lift(&regs[ip], &one, &|a, b| a.bvadd(b));
+            if let Some(next) = next.as_u64() {
+                pc = next as usize;
+                continue;
             }
-
-            if buf.len() != 4 {
-                return Err(AoCError::DirtyInput);
+            if forks == 0 {
+                break;
             }
-
-            if MAP.get(buf[0]).is_none() {
-                return Err(AoCError::DirtyInput);
+            forks -= 1;
+
+            // every value the ip register can take, whatever R0 is
+            let mut targets = vec![];
+            if !leaves(&next, &mut targets) {
+                targets.clear();
+                scratch.push();
+                while scratch.check() == z3::SatResult::Sat {
+                    let target = scratch.get_model()?.eval(&next, true)?.as_u64()?;
+                    scratch.assert(&next._eq(&bv(target)).not());
+                    targets.push(target);
+                    if targets.len() > program.len() + 1 {
+                        return None;
+                    }
+                }
+                scratch.pop(1);
             }
 
-            Ok(Instruction::OPCode([
-                *MAP.get(buf[0]).unwrap(),
-                buf[1].parse()?,
-                buf[2].parse()?,
-                buf[3].parse()?,
-            ]))
+            // a fresh constant per path instead of one deep conjunction
+            for target in targets {
+                let taken = Bool::fresh_const(&ctx, "path");
+                let jump = next._eq(&bv(target));
+                solver.assert(&taken._eq(&Bool::and(&ctx, &[&condition, &jump])));
+                paths.push((target as usize, regs.clone(), taken, steps));
+            }
+            break;
         }
     }
-}
-
-// Implement all instruction
-fn addr(instruction: &[usize], register: &mut [usize]) {
-    register[instruction[3]] = register[instruction[1]] + register[instruction[2]];
-}
-
-fn addi(instruction: &[usize], register: &mut [usize]) {
-    register[instruction[3]] = register[instruction[1]] + instruction[2];
-}
-
-fn mulr(instruction: &[usize], register: &mut [usize]) {
-    register[instruction[3]] = register[instruction[1]] * register[instruction[2]];
-}
-
-fn muli(instruction: &[usize], register: &mut [usize]) {
-    register[instruction[3]] = register[instruction[1]] * instruction[2];
-}
-
-fn banr(instruction: &[usize], register: &mut [usize]) {
-    register[instruction[3]] = register[instruction[1]] & register[instruction[2]];
-}
-
-fn bani(instruction: &[usize], register: &mut [usize]) {
-    register[instruction[3]] = register[instruction[1]] & instruction[2];
-}
-
-fn borr(instruction: &[usize], register: &mut [usize]) {
-    register[instruction[3]] = register[instruction[1]] | register[instruction[2]];
-}
+    halts.sort_by_key(|(steps, _)| *steps);
+
+    // whether R0 takes one of the first k paths, a fresh constant per path
+    // instead of one deep disjunction
+    let mut within = vec![Bool::from_bool(&ctx, false)];
+    for (k, (_, condition)) in halts.iter().enumerate() {
+        let next = Bool::new_const(&ctx, format!("within{}", k + 1));
+        solver.assert(&next._eq(&Bool::or(&ctx, &[&within[k], condition])));
+        within.push(next);
+    }
+    let bound = halts.len();
 
-fn bori(instruction: &[usize], register: &mut [usize]) {
-    register[instruction[3]] = register[instruction[1]] | instruction[2];
-}
+    // halts on a path from `from` on
+    let halts_from = |from: usize| {
+        let assumptions = [within[bound].clone(), within[from - 1].not()];
+        solver.check_assumptions(&assumptions) == z3::SatResult::Sat
+    };
+    let r0_value = || solver.get_model()?.eval(&r0, true)?.as_u64();
 
-fn setr(instruction: &[usize], register: &mut [usize]) {
-    register[instruction[3]] = register[instruction[1]];
-}
+    if bound == 0 || !halts_from(1) {
+        return None;
+    }
 
-fn seti(instruction: &[usize], register: &mut [usize]) {
-    register[instruction[3]] = instruction[1];
-}
+    // the shortest path R0 can take
+    let (mut lo, mut hi) = (1, bound);
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        if solver.check_assumptions(&[within[mid].clone()]) == z3::SatResult::Sat {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+    solver.check_assumptions(&[within[lo].clone()]);
+    let first = r0_value()?;
+
+    // the longest path R0 can take
+    let (mut lo, mut hi) = (1, bound);
+    while lo < hi {
+        let mid = (lo + hi).div_ceil(2);
+        if halts_from(mid) {
+            lo = mid;
+        } else {
+            hi = mid - 1;
+        }
+    }
+    halts_from(lo);
+    let last = r0_value()?;
 
-fn gtir(instruction: &[usize], register: &mut [usize]) {
-    register[instruction[3]] = if instruction[1] > register[instruction[2]] {
-        1
-    } else {
-        0
-    };
+    Some((first, last))
 }
 
-fn gtri(instruction: &[usize], register: &mut [usize]) {
-    register[instruction[3]] = if register[instruction[1]] > instruction[2] {
-        1
-    } else {
-        0
-    };
+/// `f` of `a` and `b`, applied inside the branches of an if-then-else
+/// instead of around it, so that where a jump goes stays in its leaves
+fn lift<'ctx>(
+    a: &BV<'ctx>,
+    b: &BV<'ctx>,
+    f: &dyn Fn(&BV<'ctx>, &BV<'ctx>) -> BV<'ctx>,
+) -> BV<'ctx> {
+    if let Some((c, t, e)) = branches(a) {
+        return c.ite(&lift(&t, b, f), &lift(&e, b, f));
+    }
+    if let Some((c, t, e)) = branches(b) {
+        return c.ite(&lift(a, &t, f), &lift(a, &e, f));
+    }
+    f(a, b).simplify()
 }
 
-fn gtrr(instruction: &[usize], register: &mut [usize]) {
-    register[instruction[3]] = if register[instruction[1]] > register[instruction[2]] {
-        1
-    } else {
-        0
-    };
+/// the condition and branches of an if-then-else
+fn branches<'ctx>(x: &BV<'ctx>) -> Option<(Bool<'ctx>, BV<'ctx>, BV<'ctx>)> {
+    if !x.is_app() || x.decl().kind() != z3::DeclKind::ITE {
+        return None;
+    }
+    let children = x.children();
+    Some((
+        children[0].as_bool()?,
+        children[1].as_bv()?,
+        children[2].as_bv()?,
+    ))
 }
 
-fn eqir(instruction: &[usize], register: &mut [usize]) {
-    register[instruction[3]] = if instruction[1] == register[instruction[2]] {
-        1
-    } else {
-        0
-    };
+/// Collect the values at the leaves of nested if-then-elses, returns false
+/// if a leaf is not a constant.
+fn leaves(x: &BV<'_>, out: &mut Vec<u64>) -> bool {
+    if let Some(v) = x.as_u64() {
+        if !out.contains(&v) {
+            out.push(v);
+        }
+        return true;
+    }
+    match branches(x) {
+        Some((_, t, e)) => leaves(&t, out) && leaves(&e, out),
+        None => false,
+    }
 }
 
-fn eqri(instruction: &[usize], register: &mut [usize]) {
-    register[instruction[3]] = if register[instruction[1]] == instruction[2] {
-        1
-    } else {
-        0
-    };
-}
+#[test]
+fn z3_matches_interpretation() {
+    // like day 21: R5 runs through a cycle, R1 = R5 * 300 / 256 by counting
+    // up, and the program halts once R1 equals R0
+    let source = "#ip 4
+seti 0 0 5
+addi 5 3 5
+muli 5 5 5
+bani 5 63 5
+muli 5 300 3
+seti 0 0 1
+addi 1 1 2
+muli 2 256 2
+gtrr 2 3 2
+addr 2 4 4
+addi 4 1 4
+seti 13 0 4
+addi 1 1 1
+seti 5 0 4
+eqrr 1 0 2
+addr 2 4 4
+seti 0 0 4";
+    let assembler = Assembler::load(source).unwrap();
+    assert!(matches!(
+        Idiom::detect(&assembler.instructions, assembler.ip, 6),
+        Some(Idiom::Division { .. })
+    ));
+
+    let mut halting = (0..100)
+        .filter_map(|r0| {
+            let mut assembler = Assembler::load(source).unwrap();
+            assembler.regs[0] = r0;
+            assembler
+                .exec_checked(100_000)
+                .ok()
+                .map(|steps| (steps, r0))
+        })
+        .collect::<Vec<_>>();
+    halting.sort_unstable();
+    assert_eq!(halting.len(), 64);
+    let (first, last) = (halting[0].1, halting[63].1);
+    assert_eq!(
+        solve(&assembler, 100, 10_000),
+        Some((first as u64, last as u64))
+    );
 
-fn eqrr(instruction: &[usize], register: &mut [usize]) {
-    register[instruction[3]] = if register[instruction[1]] == register[instruction[2]] {
-        1
-    } else {
-        0
-    };
+    // too few forks to reach the last R0 that halts
+    assert_eq!(
+        solve(&assembler, 10, 10_000).map(|(first, _)| first),
+        Some(first as u64)
+    );
 }
@@ -2,151 +2,357 @@
 
 use std::{
     cmp::Ordering,
-    collections::HashSet,
+    collections::{BTreeMap, HashMap},
+    convert::TryFrom,
     fmt::{self, Debug, Display},
     str::FromStr,
 };
 
-use aoc2018::{load, setup_logger, AoCError, Result};
+use aoc2018::{flag, load, setup_logger, write_json_lines, AoCError, Result};
 use lazy_static::lazy_static;
 use log::debug;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 
 lazy_static! {
-    static ref RE: Regex = Regex::new(r"(?P<units>\d+) units each with (?P<hp>\d+) hit points ?\(?(?P<property>[a-z|;| |,]+)?\)? with an attack that does (?P<atk>\d+) (?P<type>[a-z]+) damage at initiative (?P<initiative>\d+)").unwrap();
-    static ref WEAK: Regex = Regex::new(r"weak to ([a-z|,| ]+)").unwrap();
-    static ref IMMUNE: Regex = Regex::new(r"immune to ([a-z|,| ]+)").unwrap();
+    static ref RE: Regex = Regex::new(r"(?P<units>\d+) units each with (?P<hp>\d+) hit points(?: \((?P<property>[^()]+)\))? with an attack that does (?P<atk>\d+) (?P<type>.+) damage at initiative (?P<initiative>\d+)\s*$").unwrap();
 }
 
 fn main() -> Result<()> {
     let s = load(24);
     setup_logger()?;
 
+    let args = std::env::args().collect::<Vec<_>>();
+    if let Some(path) = value(&args, "--scenario").next() {
+        return scenario(&std::fs::read_to_string(path)?, &args);
+    }
+    if let Some(format) = value(&args, "--emit").next() {
+        return emit(&s.parse()?, format);
+    }
+
     part1(&s)?;
     part2(&s)?;
     Ok(())
 }
 
+/// the values following every `flag` in `args`
+fn value<'a>(args: &'a [String], flag: &'a str) -> impl Iterator<Item = &'a String> {
+    args.windows(2)
+        .filter(move |pair| pair[0] == flag)
+        .map(|pair| &pair[1])
+}
+
+/// Fight the factions of `--scenario <file>`, in the puzzle's format or
+/// JSON, every one against every other except for those named in an
+/// `--ally <a>,<b>`. With `--emit text` or `--emit json` the scenario is
+/// printed instead.
+fn scenario(s: &str, args: &[String]) -> Result<()> {
+    let mut battle = if s.trim_start().starts_with('{') {
+        serde_json::from_str::<Battle>(s)?
+    } else {
+        s.parse::<Battle>()?
+    };
+    for pair in value(args, "--ally") {
+        let (a, b) = pair.split_once(',').ok_or(AoCError::DirtyInput)?;
+        let faction = |name: &str| {
+            battle
+                .names
+                .iter()
+                .position(|other| other == name)
+                .ok_or(AoCError::DirtyInput)
+        };
+        let (a, b) = (faction(a)?, faction(b)?);
+        battle.set_hostile(a, b, false);
+        battle.set_hostile(b, a, false);
+    }
+    if let Some(format) = value(args, "--emit").next() {
+        return emit(&battle, format);
+    }
+    let (winner, units) = battle.clone().fight();
+    println!("{}", battle.describe(&winner, units));
+    Ok(())
+}
+
+/// Print `battle` as `text` like the puzzle's input, or as `json`.
+fn emit(battle: &Battle, format: &str) -> Result<()> {
+    match format {
+        "text" => print!("{}", battle),
+        "json" => println!("{}", serde_json::to_string_pretty(battle)?),
+        _ => return Err(AoCError::DirtyInput),
+    }
+    Ok(())
+}
+
 fn part1(s: &str) -> Result<()> {
     let mut battle = s.parse::<Battle>()?;
-    let res = battle.round();
+    if flag("--events") {
+        battle.log = Some(vec![]);
+    }
+    let res = battle.fight();
+    if let Some(events) = &battle.log {
+        write_json_lines(events, std::io::stdout().lock())?;
+    }
     println!("part1: {}", res.1);
     Ok(())
 }
 
 fn part2(s: &str) -> Result<()> {
-    let battle = s.parse::<Battle>().unwrap();
-
-    let range = find_range(s);
-    // range.1 may be deadlock, +10 to make sure we can get a solution
-    for boost in range.0..=range.1 + 10 {
-        let mut battle = battle.clone();
-        battle.boost(boost);
-        let res = battle.round();
-        if res.0 == Winner::ImmuneSystem {
-            println!(
-                "part2: find immune system win with smallest boost {}",
-                boost
-            );
-            return Ok(());
+    let battle = s.parse::<Battle>()?;
+    let (least, curve) = least_boost(&battle);
+    if flag("--curve") {
+        for (boost, winner, units) in curve.iter() {
+            println!("{:>6} {}", boost, battle.describe(winner, *units));
         }
     }
-
+    match least {
+        Some((boost, units)) => println!("part2: {} with boost {}", units, boost),
+        None => println!("part2: no boost lets the immune system win"),
+    }
     Ok(())
 }
 
-/// binary search
-/// lower trcaks the largest boost that immune system fail
-/// upper tracks the smallest boost that immune system win
-fn find_range(s: &str) -> (i64, i64) {
-    let mut lower = 0;
-    let mut upper = 10000;
+/// a boost, the winner with it and the units left
+type Outcome = (i64, Winner, i64);
 
-    let battle = s.parse::<Battle>().unwrap();
-    let mut left = vec![];
-    loop {
+/// The least boost with which the immune system wins and the units it has
+/// left then, along with the outcome of every boost from 0 up to it, or of
+/// every boost tried if there is none. Boosts past the least winning one
+/// are not fought, so the curve ends there.
+///
+/// More boost does not always help, and a boost can also leave the battle
+/// in a deadlock where no group can kill a single unit. So boosts doubling
+/// from 1 only find one that wins, and every boost from 0 up to it is tried
+/// to find the least. The doubling stops once the boost is large enough
+/// that the outcome cannot change any more.
+fn least_boost(battle: &Battle) -> (Option<(i64, i64)>, Vec<Outcome>) {
+    let fight = |boost| {
         let mut battle = battle.clone();
-        battle.boost(upper);
-        let res = battle.round();
-        if res.0 == Winner::ImmuneSystem {
-            left.push((upper, res.1));
+        battle.boost(boost);
+        let (winner, units) = battle.fight();
+        (boost, winner, units)
+    };
+
+    // from this boost on the fight goes the same way: the immune system
+    // picks targets first and in a fixed order, prefers targets by their
+    // multiplier alone, and one of its units wipes out any group it hits
+    let ratios = battle
+        .multipliers
+        .values()
+        .map(|multiplier| (multiplier.0, multiplier.1))
+        .chain(Some((1, 1)))
+        .filter(|&(numerator, _)| numerator > 0)
+        .collect::<Vec<_>>();
+    let wipe_out = battle
+        .groups
+        .iter()
+        .filter(|group| group.faction != IMMUNE_SYSTEM)
+        .flat_map(|group| {
+            let hp = group.units * group.hp;
+            ratios
+                .iter()
+                .map(move |&(numerator, denominator)| hp * denominator / numerator)
+        })
+        .max()
+        .unwrap_or(0);
+    let order = battle
+        .groups
+        .iter()
+        .map(|group| group.units * group.atk)
+        .max()
+        .unwrap_or(0);
+    let denominator = ratios.iter().map(|&(_, d)| d).max().unwrap_or(1);
+    let most = wipe_out.max(order).max(denominator * denominator) + 1;
+
+    let mut tried = vec![];
+    for boost in std::iter::successors(Some(1), |boost| Some(boost * 2))
+        .take_while(|&boost| boost < most)
+        .chain(Some(most))
+    {
+        let outcome = fight(boost);
+        let won = outcome.1.only(IMMUNE_SYSTEM);
+        tried.push(outcome);
+        if won {
             break;
-        } else {
-            lower = upper;
-            upper *= 2;
         }
     }
-    println!("[{}-{}]", lower, upper);
+    let upper = match tried.last() {
+        Some((boost, winner, _)) if winner.only(IMMUNE_SYSTEM) => *boost,
+        _ => return (None, tried),
+    };
 
-    while lower < upper {
-        let mid = (lower + upper) / 2;
-        let mut battle = battle.clone();
-        battle.boost(mid);
-        let res = battle.round();
-        match res.0 {
-            Winner::ImmuneSystem => {
-                left.push((mid, res.1));
-                upper = mid;
-            }
-            Winner::Infection => lower = mid + 1,
-            Winner::Deadlock => return (lower, upper),
+    let mut curve = vec![];
+    for boost in 0..=upper {
+        let outcome = fight(boost);
+        let won = outcome.1.only(IMMUNE_SYSTEM);
+        curve.push(outcome);
+        if won {
+            return (Some((boost, curve[boost as usize].2)), curve);
         }
-        println!("[{}-{}]", lower, upper);
     }
-    left.sort_unstable();
-    println!("{}", left[0].1);
-
-    (upper, lower)
+    unreachable!()
 }
 
-#[derive(Debug, Clone)]
+/// Groups of any number of factions. By default every faction fights every
+/// other, `set_hostile` changes who attacks whom.
+///
+/// It reads and prints as the puzzle's input, and serialises through
+/// `Scenario`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(into = "Scenario", try_from = "Scenario")]
 struct Battle {
-    groups: [Vec<Group>; 2],
+    names: Vec<String>,
+    groups: Vec<Group>,
+    /// `hostile[a][b]` if groups of faction `a` attack those of faction `b`
+    hostile: Vec<Vec<bool>>,
+    /// rounds fought so far
+    round: usize,
+    /// what happened in each round, if recorded
+    log: Option<Vec<Event>>,
+    /// how a modifier such as `weak` scales the damage of the types it lists
+    multipliers: HashMap<String, Multiplier>,
 }
 
 impl Battle {
-    fn round(&mut self) -> (Winner, i64) {
-        let mut count = 0;
+    /// Number the groups within their factions and check that the
+    /// multipliers cover their modifiers, and that every name fits the
+    /// text format.
+    fn new(
+        names: Vec<String>,
+        mut groups: Vec<Group>,
+        multipliers: HashMap<String, Multiplier>,
+    ) -> Result<Self> {
+        for index in 0..groups.len() {
+            let faction = groups[index].faction;
+            if faction >= names.len() {
+                return Err(AoCError::DirtyInput);
+            }
+            groups[index].number = 1 + groups[..index]
+                .iter()
+                .filter(|other| other.faction == faction)
+                .count();
+            groups[index].ep = groups[index].units * groups[index].atk;
+        }
+        if groups
+            .iter()
+            .flat_map(|group| group.modifiers.iter())
+            .any(|modifier| !multipliers.contains_key(&modifier.name))
+        {
+            return Err(AoCError::DirtyInput);
+        }
+        let faction_names = names.iter().enumerate().all(|(index, name)| {
+            word(name, &[":", ",", "=", "->"]) && !names[..index].contains(name)
+        });
+        let modifier_names = multipliers
+            .keys()
+            .all(|name| word(name, &["(", ")", ";", ",", "=", " to "]));
+        let types = groups.iter().all(|group| {
+            word(&group.atk_type, &[])
+                && group.modifiers.iter().all(|modifier| {
+                    !modifier.types.is_empty()
+                        && modifier
+                            .types
+                            .iter()
+                            .all(|name| word(name, &["(", ")", ";", ","]))
+                })
+        });
+        if !(faction_names && modifier_names && types) {
+            return Err(AoCError::DirtyInput);
+        }
+        let hostile = (0..names.len())
+            .map(|a| (0..names.len()).map(|b| a != b).collect())
+            .collect();
+        Ok(Battle {
+            names,
+            groups,
+            hostile,
+            round: 0,
+            log: None,
+            multipliers,
+        })
+    }
+
+    /// Fight until no group attacks any other, and return the winner and
+    /// the units left.
+    fn fight(&mut self) -> (Winner, i64) {
         while !self.update() {
+            self.round += 1;
             self.choose_target();
             if !self.attcack() {
                 // dead lock
                 return (Winner::Deadlock, 0);
             }
-            count += 1;
-            if count % 100 == 0 {
+            if self.round.is_multiple_of(100) {
                 debug!("\n{}", self);
             }
         }
-        for (idx, groups) in self.groups.iter().enumerate() {
-            if !groups.is_empty() {
-                return (
-                    idx.into(),
-                    groups.iter().map(|group| group.units).sum::<i64>(),
-                );
+        let mut factions = self
+            .groups
+            .iter()
+            .map(|group| group.faction)
+            .collect::<Vec<_>>();
+        factions.sort_unstable();
+        factions.dedup();
+        (
+            Winner::Factions(factions),
+            self.groups.iter().map(|group| group.units).sum::<i64>(),
+        )
+    }
+
+    /// `winner` with the names of the factions
+    fn describe(&self, winner: &Winner, units: i64) -> String {
+        match winner {
+            Winner::Factions(factions) => {
+                let names = factions
+                    .iter()
+                    .map(|&faction| self.names[faction].as_str())
+                    .collect::<Vec<_>>();
+                format!("{} win with {} units", names.join(", "), units)
             }
+            Winner::Deadlock => "deadlock".to_string(),
         }
-        (1.into(), 0)
     }
 
-    fn choose_target(&mut self) {
-        let mut seq = vec![];
-        for i in 0..2 {
-            for j in 0..self.groups[i].len() {
-                seq.push((i, j));
-            }
+    fn id(&self, index: usize) -> GroupId {
+        GroupId {
+            faction: self.groups[index].faction,
+            group: self.groups[index].number,
+        }
+    }
+
+    fn damage(&self, attacker: &Group, defender: &Group) -> i64 {
+        match defender
+            .modifiers
+            .iter()
+            .find(|modifier| modifier.types.contains(&attacker.atk_type))
+        {
+            Some(modifier) => self.multipliers[&modifier.name].apply(attacker.ep),
+            None => attacker.ep,
+        }
+    }
+
+    fn emit(&mut self, event: Event) {
+        if let Some(log) = self.log.as_mut() {
+            log.push(event);
         }
-        seq.sort_unstable_by(|a, b| cmp(&self.groups[a.0][a.1], &self.groups[b.0][b.1]));
-        for group in seq.iter().rev() {
-            let cur = group.0;
-            let attacker = &self.groups[cur][group.1];
+    }
+
+    fn set_hostile(&mut self, attacker: usize, defender: usize, hostile: bool) {
+        self.hostile[attacker][defender] = hostile;
+    }
+
+    fn choose_target(&mut self) {
+        let mut seq = (0..self.groups.len()).collect::<Vec<_>>();
+        seq.sort_unstable_by(|&a, &b| cmp(&self.groups[a], &self.groups[b]));
+        for &index in seq.iter().rev() {
+            let attacker = &self.groups[index];
             let mut targets = vec![];
 
-            for (idx, defender) in self.groups[1 - cur].iter().enumerate() {
-                if defender.targeted.is_some() {
+            for (idx, defender) in self.groups.iter().enumerate() {
+                if !self.hostile[attacker.faction][defender.faction] || defender.targeted.is_some()
+                {
                     continue;
                 }
-                let damage = damage(attacker, defender);
+                let damage = self.damage(attacker, defender);
                 if damage == 0 {
                     continue;
                 }
@@ -169,140 +375,371 @@ impl Battle {
             if targets.is_empty() {
                 continue;
             }
-            targets.sort_unstable_by(|a, b| {
-                cmp(&self.groups[1 - cur][a.0], &self.groups[1 - cur][b.0])
+            targets.sort_unstable_by(|a, b| cmp(&self.groups[a.0], &self.groups[b.0]));
+            let &(target, damage) = targets.last().unwrap();
+            self.groups[index].target = Some(target);
+            self.groups[target].targeted = Some(index);
+            self.emit(Event::Targeted {
+                round: self.round,
+                attacker: self.id(index),
+                defender: self.id(target),
+                damage,
             });
-            let target = targets.last().unwrap();
-            self.groups[cur][group.1].target = Some(target.0);
-            self.groups[1 - cur][target.0].targeted = Some(group.1);
         }
     }
 
     fn attcack(&mut self) -> bool {
         let mut flag = false;
-        let mut seq = vec![];
-        for i in 0..2 {
-            for j in 0..self.groups[i].len() {
-                seq.push((i, j));
-            }
-        }
-        seq.sort_unstable_by(|a, b| {
-            self.groups[a.0][a.1]
-                .initiative
-                .cmp(&self.groups[b.0][b.1].initiative)
-        });
+        let mut seq = (0..self.groups.len()).collect::<Vec<_>>();
+        seq.sort_unstable_by_key(|&index| self.groups[index].initiative);
 
-        for group in seq.iter().rev() {
-            let cur = group.0;
-            let attacker = &mut self.groups[cur][group.1];
+        for &index in seq.iter().rev() {
+            let attacker = &mut self.groups[index];
             if attacker.units <= 0 || attacker.target.is_none() {
                 continue;
             }
             // update ep
             attacker.ep = attacker.units * attacker.atk;
             let target = attacker.target.unwrap();
-            let damage = damage(&self.groups[cur][group.1], &self.groups[1 - cur][target]);
-            let dead = damage / self.groups[1 - cur][target].hp;
+            let damage = self.damage(&self.groups[index], &self.groups[target]);
+            let dead = damage / self.groups[target].hp;
             // if nobody die, this battle is in deadlock, break
             if dead > 0 {
                 flag = true;
             }
 
-            self.groups[1 - cur][target].units -= dead;
+            let killed = dead.min(self.groups[target].units);
+            self.groups[target].units -= dead;
+            self.emit(Event::Attacked {
+                round: self.round,
+                attacker: self.id(index),
+                defender: self.id(target),
+                killed,
+            });
+            if self.groups[target].units <= 0 {
+                self.emit(Event::Eliminated {
+                    round: self.round,
+                    group: self.id(target),
+                });
+            }
         }
         flag
     }
 
+    /// Remove the dead and returns whether the battle is over.
     fn update(&mut self) -> bool {
-        let mut flag = false;
-        for i in 0..2 {
-            self.groups[i].retain(|group| group.units > 0);
-            if self.groups[i].is_empty() {
-                flag = true;
-            }
-            for group in self.groups[i].iter_mut() {
-                group.target = None;
-                group.targeted = None;
-                group.ep = group.units * group.atk;
-            }
+        self.groups.retain(|group| group.units > 0);
+        for group in self.groups.iter_mut() {
+            group.target = None;
+            group.targeted = None;
+            group.ep = group.units * group.atk;
         }
-        flag
+        !self.groups.iter().any(|attacker| {
+            self.groups
+                .iter()
+                .any(|defender| self.hostile[attacker.faction][defender.faction])
+        })
     }
 
     fn boost(&mut self, boost: i64) {
-        for group in self.groups[0].iter_mut() {
+        for group in self
+            .groups
+            .iter_mut()
+            .filter(|group| group.faction == IMMUNE_SYSTEM)
+        {
             group.atk += boost;
         }
     }
 }
 
+/// The puzzle's input, after a `Multipliers:` line for those that are not
+/// the puzzle's.
 impl Display for Battle {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "Immune system: ")?;
-        for group in self.groups[0].iter() {
-            writeln!(f, "{}", group)?;
+        let default = default_multipliers();
+        let changed = self
+            .multipliers
+            .iter()
+            .filter(|(modifier, multiplier)| default.get(*modifier) != Some(*multiplier))
+            .collect::<BTreeMap<_, _>>();
+        if !changed.is_empty() {
+            let list = changed
+                .iter()
+                .map(|(modifier, multiplier)| format!("{} = {}", modifier, multiplier))
+                .collect::<Vec<_>>();
+            writeln!(f, "Multipliers: {}", list.join(", "))?;
+        }
+        let mut changed = vec![];
+        for (a, row) in self.hostile.iter().enumerate() {
+            for (b, &hostile) in row.iter().enumerate() {
+                if hostile != (a != b) {
+                    changed.push(format!(
+                        "{} -> {} = {}",
+                        self.names[a], self.names[b], hostile
+                    ));
+                }
+            }
         }
-        writeln!(f, "Infection: ")?;
-        for group in self.groups[1].iter() {
-            writeln!(f, "{}", group)?;
+        if !changed.is_empty() {
+            writeln!(f, "Hostile: {}", changed.join(", "))?;
+        }
+        for (faction, name) in self.names.iter().enumerate() {
+            if faction > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "{}:", name)?;
+            for group in self.groups.iter().filter(|group| group.faction == faction) {
+                writeln!(f, "{}", group)?;
+            }
         }
         Ok(())
     }
 }
 
-fn damage(attacker: &Group, defender: &Group) -> i64 {
-    if defender.immunes.contains(&attacker.atk_type) {
-        0
-    } else if defender.weaks.contains(&attacker.atk_type) {
-        2 * attacker.ep
-    } else {
-        attacker.ep
+/// `numerator / denominator`, rounding down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+struct Multiplier(i64, i64);
+
+impl Multiplier {
+    fn apply(self, damage: i64) -> i64 {
+        damage * self.0 / self.1
+    }
+}
+
+impl FromStr for Multiplier {
+    type Err = AoCError;
+
+    /// `2` or `1/2`
+    fn from_str(s: &str) -> Result<Self> {
+        let multiplier = match s.split_once('/') {
+            Some((numerator, denominator)) => {
+                Multiplier(numerator.trim().parse()?, denominator.trim().parse()?)
+            }
+            None => Multiplier(s.trim().parse()?, 1),
+        };
+        if multiplier.0 < 0 || multiplier.1 <= 0 {
+            return Err(AoCError::DirtyInput);
+        }
+        Ok(multiplier)
+    }
+}
+
+impl Display for Multiplier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.1 == 1 {
+            write!(f, "{}", self.0)
+        } else {
+            write!(f, "{}/{}", self.0, self.1)
+        }
+    }
+}
+
+impl From<Multiplier> for String {
+    fn from(multiplier: Multiplier) -> Self {
+        multiplier.to_string()
+    }
+}
+
+impl TryFrom<String> for Multiplier {
+    type Error = AoCError;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
     }
 }
 
+/// the puzzle's: weak doubles damage and immune stops it
+fn default_multipliers() -> HashMap<String, Multiplier> {
+    [("weak", Multiplier(2, 1)), ("immune", Multiplier(0, 1))]
+        .into_iter()
+        .map(|(modifier, multiplier)| (modifier.to_string(), multiplier))
+        .collect()
+}
+
 impl FromStr for Battle {
     type Err = AoCError;
 
+    /// Every line ending with a colon starts a faction, the groups after it
+    /// belong to that faction. A line like `Multipliers: resistant = 1/2,
+    /// weak = 3` adds modifiers or changes those of the puzzle, and one like
+    /// `Hostile: Infection -> Immune System = false` changes who attacks whom.
     fn from_str(s: &str) -> Result<Self> {
-        let mut immune_system = vec![];
-        let mut infection = vec![];
-        let mut p = &mut immune_system;
+        let mut names = vec![];
+        let mut groups: Vec<Group> = vec![];
+        let mut multipliers = default_multipliers();
+        let mut hostility = vec![];
         for s in s.lines() {
-            if s.starts_with("Immune") {
-                p = &mut immune_system;
+            // a faction can still be called `Hostile` or `Multipliers`
+            let list = |prefix| {
+                s.strip_prefix(prefix)
+                    .filter(|list| !list.trim().is_empty())
+            };
+            if let Some(list) = list("Hostile:") {
+                for entry in list.split(',').filter(|entry| !entry.trim().is_empty()) {
+                    let (pair, hostile) = entry.split_once('=').ok_or(AoCError::DirtyInput)?;
+                    let (attacker, defender) = pair.split_once("->").ok_or(AoCError::DirtyInput)?;
+                    let hostile = hostile
+                        .trim()
+                        .parse::<bool>()
+                        .map_err(|_| AoCError::DirtyInput)?;
+                    hostility.push((
+                        attacker.trim().to_string(),
+                        defender.trim().to_string(),
+                        hostile,
+                    ));
+                }
                 continue;
             }
-            if s.starts_with("Infection:") {
-                p = &mut infection;
+            if let Some(list) = list("Multipliers:") {
+                for entry in list.split(',').filter(|entry| !entry.trim().is_empty()) {
+                    let (modifier, multiplier) =
+                        entry.split_once('=').ok_or(AoCError::DirtyInput)?;
+                    multipliers.insert(modifier.trim().to_string(), multiplier.parse()?);
+                }
+                continue;
+            }
+            if let Some(name) = s.strip_suffix(':') {
+                names.push(name.to_string());
                 continue;
             }
             if s.is_empty() {
                 continue;
+            } else if names.is_empty() {
+                return Err(AoCError::DirtyInput);
             } else {
-                p.push(s.parse::<Group>()?);
+                let mut group = s.parse::<Group>()?;
+                group.faction = names.len() - 1;
+                groups.push(group);
             }
         }
-        Ok(Battle {
-            groups: [immune_system, infection],
-        })
+        let mut battle = Battle::new(names, groups, multipliers)?;
+        for (attacker, defender, hostile) in hostility {
+            let faction = |name: &str| battle.names.iter().position(|other| other == name);
+            let attacker = faction(&attacker).ok_or(AoCError::DirtyInput)?;
+            let defender = faction(&defender).ok_or(AoCError::DirtyInput)?;
+            battle.set_hostile(attacker, defender, hostile);
+        }
+        Ok(battle)
+    }
+}
+
+/// A battle as stored in JSON. Multipliers not given are the puzzle's, and
+/// without `hostile` every faction fights every other.
+#[derive(Serialize, Deserialize)]
+struct Scenario {
+    #[serde(default)]
+    multipliers: BTreeMap<String, Multiplier>,
+    factions: Vec<Faction>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hostile: Option<Vec<Vec<bool>>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Faction {
+    name: String,
+    groups: Vec<Group>,
+}
+
+impl From<Battle> for Scenario {
+    fn from(battle: Battle) -> Self {
+        let everyone = battle.hostile.iter().enumerate().all(|(a, row)| {
+            row.iter()
+                .enumerate()
+                .all(|(b, &hostile)| hostile == (a != b))
+        });
+        let hostile = Some(battle.hostile).filter(|_| !everyone);
+        let mut factions = battle
+            .names
+            .into_iter()
+            .map(|name| Faction {
+                name,
+                groups: vec![],
+            })
+            .collect::<Vec<_>>();
+        for group in battle.groups {
+            factions[group.faction].groups.push(group);
+        }
+        Scenario {
+            multipliers: battle.multipliers.into_iter().collect(),
+            factions,
+            hostile,
+        }
     }
 }
 
-#[derive(Debug)]
+impl TryFrom<Scenario> for Battle {
+    type Error = AoCError;
+
+    fn try_from(scenario: Scenario) -> Result<Self> {
+        let mut names = vec![];
+        let mut groups = vec![];
+        for (faction, army) in scenario.factions.into_iter().enumerate() {
+            names.push(army.name);
+            groups.extend(
+                army.groups
+                    .into_iter()
+                    .map(|group| Group { faction, ..group }),
+            );
+        }
+        let mut multipliers = default_multipliers();
+        multipliers.extend(scenario.multipliers);
+        let mut battle = Battle::new(names, groups, multipliers)?;
+        if let Some(hostile) = scenario.hostile {
+            let n = battle.names.len();
+            if hostile.len() != n || hostile.iter().any(|row| row.len() != n) {
+                return Err(AoCError::DirtyInput);
+            }
+            battle.hostile = hostile;
+        }
+        Ok(battle)
+    }
+}
+
+/// In JSON without what follows from the faction it is listed under or
+/// changes while fighting.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 struct Group {
+    #[serde(skip)]
+    faction: usize,
+    /// counting from 1 within the faction, in the order of the input
+    #[serde(skip)]
+    number: usize,
     units: i64,
+    #[serde(rename = "hit_points")]
     hp: i64,
-    weaks: HashSet<Property>,
-    immunes: HashSet<Property>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    modifiers: Vec<Modifier>,
+    #[serde(rename = "attack")]
     atk: i64,
-    atk_type: Property,
+    #[serde(rename = "damage_type")]
+    atk_type: String,
     initiative: u64,
+    #[serde(skip)]
     ep: i64,
 
+    #[serde(skip)]
     target: Option<usize>,
+    #[serde(skip)]
     targeted: Option<usize>,
 }
 
+/// Whether `s` can be written in the text format where it would be cut
+/// short by any of `separators`.
+fn word(s: &str, separators: &[&str]) -> bool {
+    !s.is_empty()
+        && s.trim() == s
+        && !s.contains('\n')
+        && separators.iter().all(|separator| !s.contains(separator))
+}
+
+/// the damage types under a modifier, such as `weak` to `fire`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Modifier {
+    name: String,
+    types: Vec<String>,
+}
+
 fn cmp(a: &Group, b: &Group) -> Ordering {
     if a.ep == b.ep {
         a.initiative.cmp(&b.initiative)
@@ -319,30 +756,27 @@ impl FromStr for Group {
             let units = caps["units"].parse()?;
             let hp = caps["hp"].parse()?;
             let atk = caps["atk"].parse()?;
-            let atk_type = caps["type"].parse()?;
+            let atk_type = caps["type"].to_string();
             let initiative = caps["initiative"].parse()?;
 
-            let mut weaks = HashSet::new();
-            let mut immunes = HashSet::new();
+            let mut modifiers = vec![];
             if let Some(properties) = &caps.name("property") {
                 for property in properties.as_str().split("; ") {
-                    if let Some(weak) = WEAK.captures(property) {
-                        for weak in weak[1].split(", ") {
-                            weaks.insert(weak.parse::<Property>()?);
-                        }
-                    }
-                    if let Some(immune) = IMMUNE.captures(property) {
-                        for immune in immune[1].split(", ") {
-                            immunes.insert(immune.parse::<Property>()?);
-                        }
-                    }
+                    let (modifier, types) =
+                        property.split_once(" to ").ok_or(AoCError::DirtyInput)?;
+                    let types = types.split(", ").map(str::to_string).collect();
+                    modifiers.push(Modifier {
+                        name: modifier.to_string(),
+                        types,
+                    });
                 }
             }
             Ok(Group {
+                faction: 0,
+                number: 1,
                 units,
                 hp,
-                weaks,
-                immunes,
+                modifiers,
                 atk,
                 atk_type,
                 initiative,
@@ -357,76 +791,295 @@ impl FromStr for Group {
     }
 }
 
-impl Clone for Group {
-    fn clone(&self) -> Self {
-        Self {
-            units: self.units,
-            hp: self.hp,
-            weaks: self.weaks.union(&HashSet::new()).cloned().collect(),
-            immunes: self.immunes.union(&HashSet::new()).cloned().collect(),
-            atk: self.atk,
-            atk_type: self.atk_type,
-            initiative: self.initiative,
-            ep: self.ep,
-            target: self.target,
-            targeted: self.targeted,
+/// the sentence of the puzzle's input
+impl Display for Group {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} units each with {} hit points", self.units, self.hp)?;
+        if !self.modifiers.is_empty() {
+            let modifiers = self
+                .modifiers
+                .iter()
+                .map(|modifier| format!("{} to {}", modifier.name, modifier.types.join(", ")))
+                .collect::<Vec<_>>();
+            write!(f, " ({})", modifiers.join("; "))?;
         }
+        write!(
+            f,
+            " with an attack that does {} {} damage at initiative {}",
+            self.atk, self.atk_type, self.initiative
+        )
     }
 }
 
-impl Display for Group {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("Group")
-            .field("units", &self.units)
-            .field("hp", &self.hp)
-            .field("weaks", &self.weaks)
-            .field("immunes", &self.immunes)
-            .field("atk", &self.atk)
-            .field("atk_type", &self.atk_type)
-            .field("initiative", &self.initiative)
-            .field("ep", &self.ep)
-            .finish()
-    }
+/// Which group, the same all through a battle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct GroupId {
+    faction: usize,
+    /// `Group::number`
+    group: usize,
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
-enum Property {
-    Fire,
-    Cold,
-    Radiation,
-    Slashing,
-    Bludgeoning,
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event {
+    /// `attacker` picks `defender`, expecting to deal `damage`
+    Targeted {
+        round: usize,
+        attacker: GroupId,
+        defender: GroupId,
+        damage: i64,
+    },
+    /// in the order of initiative
+    Attacked {
+        round: usize,
+        attacker: GroupId,
+        defender: GroupId,
+        killed: i64,
+    },
+    /// follows the attack that killed the last unit of `group`
+    Eliminated { round: usize, group: GroupId },
 }
 
-impl FromStr for Property {
-    type Err = AoCError;
+#[derive(Debug, PartialEq)]
+enum Winner {
+    /// the factions left, which do not attack each other
+    Factions(Vec<usize>),
+    Deadlock,
+}
 
-    fn from_str(s: &str) -> Result<Self> {
-        let res = match s {
-            "fire" => Property::Fire,
-            "cold" => Property::Cold,
-            "radiation" => Property::Radiation,
-            "slashing" => Property::Slashing,
-            "bludgeoning" => Property::Bludgeoning,
-            _ => return Err(AoCError::DirtyInput),
-        };
-        Ok(res)
+impl Winner {
+    /// whether `faction` is the only one left
+    fn only(&self, faction: usize) -> bool {
+        matches!(self, Winner::Factions(factions) if factions[..] == [faction])
     }
 }
 
-#[derive(PartialEq)]
-enum Winner {
-    ImmuneSystem,
-    Infection,
-    Deadlock,
+/// the first army of the input
+const IMMUNE_SYSTEM: usize = 0;
+
+#[cfg(test)]
+const EXAMPLE: &str = "Immune System:
+17 units each with 5390 hit points (weak to radiation, bludgeoning) with an attack that does 4507 fire damage at initiative 2
+989 units each with 1274 hit points (immune to fire; weak to bludgeoning, slashing) with an attack that does 25 slashing damage at initiative 3
+
+Infection:
+801 units each with 4706 hit points (weak to radiation) with an attack that does 116 bludgeoning damage at initiative 1
+4485 units each with 2961 hit points (immune to radiation; weak to fire, cold) with an attack that does 12 slashing damage at initiative 4";
+
+#[test]
+fn example() {
+    let battle = EXAMPLE.parse::<Battle>().unwrap();
+    assert_eq!(battle.clone().fight(), (Winner::Factions(vec![1]), 5216));
+
+    let mut boosted = battle;
+    boosted.boost(1570);
+    assert_eq!(boosted.fight(), (Winner::Factions(vec![IMMUNE_SYSTEM]), 51));
+}
+
+#[test]
+fn allied_factions() {
+    // every group a faction of its own, allied with its old army
+    let mut lines = EXAMPLE.lines().filter(|line| line.contains("units"));
+    let mut s = String::new();
+    for name in ["A", "B", "C", "D"] {
+        s.push_str(&format!("{}:\n{}\n", name, lines.next().unwrap()));
+    }
+    let mut battle = s.parse::<Battle>().unwrap();
+    for (a, b) in [(0, 1), (1, 0), (2, 3), (3, 2)] {
+        battle.set_hostile(a, b, false);
+    }
+    assert_eq!(battle.fight(), (Winner::Factions(vec![2, 3]), 5216));
 }
 
-impl From<usize> for Winner {
-    fn from(idx: usize) -> Self {
-        match idx {
-            0 => Winner::ImmuneSystem,
-            1 => Winner::Infection,
-            _ => unreachable!(),
+#[test]
+fn least_boost_of_example() {
+    let battle = EXAMPLE.parse::<Battle>().unwrap();
+    let (least, curve) = least_boost(&battle);
+    assert_eq!(least, Some((1570, 51)));
+    assert_eq!(curve.len(), 1571);
+    assert!(curve[..1570]
+        .iter()
+        .all(|(_, winner, _)| !winner.only(IMMUNE_SYSTEM)));
+
+    // the infection cannot be hurt by cold, so the immune system never wins
+    let s = "Immune System:
+10 units each with 10 hit points with an attack that does 10 cold damage at initiative 2
+
+Infection:
+10 units each with 10 hit points (immune to cold) with an attack that does 1 fire damage at initiative 1";
+    let (least, curve) = least_boost(&s.parse::<Battle>().unwrap());
+    assert_eq!(least, None);
+    assert_eq!(curve.len(), 8);
+    assert!(curve.iter().all(|(_, winner, _)| winner.only(1)));
+}
+
+#[test]
+fn example_events() {
+    let mut battle = EXAMPLE.parse::<Battle>().unwrap();
+    battle.log = Some(vec![]);
+    battle.fight();
+    let events = battle.log.unwrap();
+
+    let (immune, infection) = (
+        |group| GroupId { faction: 0, group },
+        |group| GroupId { faction: 1, group },
+    );
+    let targeted = |attacker, defender, damage| Event::Targeted {
+        round: 1,
+        attacker,
+        defender,
+        damage,
+    };
+    let attacked = |attacker, defender, killed| Event::Attacked {
+        round: 1,
+        attacker,
+        defender,
+        killed,
+    };
+    // the choices of the puzzle, in the order of effective power
+    assert_eq!(
+        events[..8],
+        [
+            targeted(infection(1), immune(1), 185832),
+            targeted(immune(1), infection(2), 153238),
+            targeted(infection(2), immune(2), 107640),
+            targeted(immune(2), infection(1), 24725),
+            attacked(infection(2), immune(2), 84),
+            attacked(immune(2), infection(1), 4),
+            attacked(immune(1), infection(2), 51),
+            attacked(infection(1), immune(1), 17),
+        ]
+    );
+    assert_eq!(
+        events[8],
+        Event::Eliminated {
+            round: 1,
+            group: immune(1)
         }
+    );
+
+    let mut json = vec![];
+    write_json_lines(&events, &mut json).unwrap();
+    let read = String::from_utf8(json)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<Event>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(read, events);
+}
+
+#[test]
+fn custom_multipliers() {
+    let s = "Multipliers: resistant = 1/2, critically weak = 4
+Heroes:
+10 units each with 10 hit points (resistant to acid; critically weak to frost) with an attack that does 10 acid damage at initiative 2
+
+Villains:
+10 units each with 10 hit points (resistant to acid, frost) with an attack that does 10 frost damage at initiative 1";
+    let battle = s.parse::<Battle>().unwrap();
+    let (heroes, villains) = (&battle.groups[0], &battle.groups[1]);
+    assert_eq!(battle.damage(heroes, villains), 50);
+    assert_eq!(battle.damage(villains, heroes), 400);
+
+    // the puzzle's multipliers stay unless replaced
+    let weak = "Multipliers: weak = 3\n".to_string() + EXAMPLE;
+    let battle = weak.parse::<Battle>().unwrap();
+    assert_eq!(
+        battle.damage(&battle.groups[2], &battle.groups[0]),
+        3 * 92916
+    );
+    assert_eq!(
+        battle.damage(&battle.groups[0], &battle.groups[3]),
+        3 * 76619
+    );
+    assert_eq!(battle.damage(&battle.groups[0], &battle.groups[1]), 0);
+
+    let unknown = "A:\n1 units each with 1 hit points (brittle to fire) with an attack that does 1 fire damage at initiative 1";
+    assert!(unknown.parse::<Battle>().is_err());
+
+    // damage types and modifiers are any words, not just the puzzle's
+    let s = "Multipliers: Very Weak = 5
+Dragons:
+10 units each with 10 hit points (weak to Fire; Very Weak to ice-lance, cold) with an attack that does 10 fire-breath damage at initiative 2
+
+Knights:
+10 units each with 10 hit points (immune to fire-breath) with an attack that does 10 ice-lance damage at initiative 1";
+    let battle = s.parse::<Battle>().unwrap();
+    let (dragons, knights) = (&battle.groups[0], &battle.groups[1]);
+    assert_eq!(dragons.atk_type, "fire-breath");
+    assert_eq!(dragons.modifiers[0].types, ["Fire"]);
+    assert_eq!(battle.damage(dragons, knights), 0);
+    assert_eq!(battle.damage(knights, dragons), 500);
+    assert_eq!(battle.to_string(), s.to_string() + "\n");
+}
+
+#[test]
+fn round_trips() {
+    let battle = EXAMPLE.parse::<Battle>().unwrap();
+    assert_eq!(battle.to_string(), EXAMPLE.to_string() + "\n");
+
+    let json = serde_json::to_string(&battle).unwrap();
+    assert_eq!(serde_json::from_str::<Battle>(&json).unwrap(), battle);
+
+    let s = "Multipliers: critically weak = 4, resistant = 1/2
+Heroes:
+10 units each with 10 hit points (resistant to acid; critically weak to frost) with an attack that does 10 acid damage at initiative 2
+
+Villains:
+10 units each with 10 hit points with an attack that does 10 frost damage at initiative 1
+";
+    let mut battle = s.parse::<Battle>().unwrap();
+    assert_eq!(battle.to_string(), s);
+
+    battle.set_hostile(1, 0, false);
+    let text = battle.to_string();
+    assert_eq!(
+        text.lines().nth(1),
+        Some("Hostile: Villains -> Heroes = false")
+    );
+    assert_eq!(text.parse::<Battle>().unwrap(), battle);
+    let json = serde_json::to_string(&battle).unwrap();
+    let read = serde_json::from_str::<Battle>(&json).unwrap();
+    assert_eq!(read, battle);
+    assert!("Hostile: Villains -> Nobody = false\nVillains:"
+        .parse::<Battle>()
+        .is_err());
+
+    let edited = json.replace("\"units\":10", "\"units\":20");
+    let read = serde_json::from_str::<Battle>(&edited).unwrap();
+    assert_eq!(read.groups[0].units, 20);
+    assert_eq!(read.groups[0].ep, 200);
+
+    let unknown = json.replace("\"name\":\"resistant\"", "\"name\":\"brittle\"");
+    assert!(serde_json::from_str::<Battle>(&unknown).is_err());
+
+    // what JSON accepts prints as text that reads back the same
+    let breath = json.replace(
+        "\"damage_type\":\"acid\"",
+        "\"damage_type\":\"fire-breath\"",
+    );
+    let read = serde_json::from_str::<Battle>(&breath).unwrap();
+    assert_eq!(read.to_string().parse::<Battle>().unwrap(), read);
+    for (from, to) in [
+        ("\"damage_type\":\"acid\"", "\"damage_type\":\"acid\\n\""),
+        ("\"types\":[\"acid\"]", "\"types\":[\"acid, frost\"]"),
+        ("\"types\":[\"acid\"]", "\"types\":[]"),
+        ("\"name\":\"Heroes\"", "\"name\":\"Heroes -> Villains\""),
+        ("\"name\":\"Heroes\"", "\"name\":\"Villains\""),
+    ] {
+        let edited = json.replace(from, to);
+        assert_ne!(edited, json);
+        assert!(serde_json::from_str::<Battle>(&edited).is_err(), "{}", to);
     }
+
+    // the directives need a list, so factions can share their names
+    let s = "Hostile:
+10 units each with 10 hit points with an attack that does 10 acid damage at initiative 2
+
+Multipliers:
+10 units each with 10 hit points with an attack that does 10 frost damage at initiative 1
+";
+    let battle = s.parse::<Battle>().unwrap();
+    assert_eq!(battle.names, ["Hostile", "Multipliers"]);
+    assert_eq!(battle.to_string(), s);
 }
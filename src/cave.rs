@@ -0,0 +1,909 @@
+//! The cave battle between elves and goblins from day 15.
+//!
+//! A `Battle` owns its map and units, so it can be cloned at any point
+//! between rounds to explore what would happen with different parameters.
+//! Hit points, attack powers, the races and who fights whom come from
+//! `Rules`, which default to the puzzle's.
+//!
+//! With the log enabled a battle records every move, attack and death as an
+//! `Event`, which can be written as JSON lines and replayed on the initial
+//! state to get back to any point of the battle. `Battle::report` tells how
+//! every unit has fared so far, and a `Viewer` plays it back in a terminal.
+
+mod event;
+mod report;
+mod view;
+
+pub use event::{read_events, Event};
+pub use report::{RaceSummary, Report, UnitReport};
+pub use view::{render, Key, Keys, Viewer};
+
+use report::Tally;
+
+use crate::{AoCError, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp,
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+    fmt::{self, Debug},
+};
+
+type Map = Vec<Vec<Slot>>;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
+pub struct Point(pub usize, pub usize);
+
+impl PartialOrd for Point {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Point {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        match self.1.cmp(&other.1) {
+            cmp::Ordering::Equal => self.0.cmp(&other.0),
+            res => res,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Unit {
+    /// index in the initial reading order
+    pub id: usize,
+    pub race: Race,
+    pub hitpoint: i32,
+    pub attack: i32,
+    pub loc: Point,
+}
+
+impl Debug for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f)?;
+        write!(f, "{:?}({}) at {:?}", self.race, self.hitpoint, self.loc)?;
+        Ok(())
+    }
+}
+
+impl Unit {
+    fn new(id: usize, race: Race, loc: Point, stats: Stats) -> Self {
+        Self {
+            id,
+            race,
+            hitpoint: stats.hitpoint,
+            attack: stats.attack,
+            loc,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Stats {
+    pub hitpoint: i32,
+    pub attack: i32,
+}
+
+/// Which races take part in a battle, how strong their units start, and
+/// which of them are hostile to each other.
+#[derive(Debug, Clone)]
+pub struct Rules {
+    races: Vec<(Race, Stats)>,
+    hostile: HashSet<(Race, Race)>,
+}
+
+impl Rules {
+    /// Elves and goblins with 200 hit points and attack 3, hostile to each
+    /// other.
+    pub fn standard() -> Self {
+        let stats = Stats {
+            hitpoint: 200,
+            attack: 3,
+        };
+        Rules {
+            races: vec![],
+            hostile: HashSet::new(),
+        }
+        .with_race(Race::Elf, stats)
+        .with_race(Race::Goblin, stats)
+        .with_hostility(Race::Elf, Race::Goblin)
+    }
+
+    /// Add `race` or replace its stats. A new race is at peace with every
+    /// other until `with_hostility` says otherwise.
+    pub fn with_race(mut self, race: Race, stats: Stats) -> Self {
+        match self.races.iter_mut().find(|(other, _)| *other == race) {
+            Some((_, old)) => *old = stats,
+            None => self.races.push((race, stats)),
+        }
+        self
+    }
+
+    /// Make `a` and `b` attack each other.
+    pub fn with_hostility(mut self, a: Race, b: Race) -> Self {
+        self.hostile.insert((a, b));
+        self.hostile.insert((b, a));
+        self
+    }
+
+    pub fn stats(&self, race: Race) -> Option<Stats> {
+        self.races
+            .iter()
+            .find(|(other, _)| *other == race)
+            .map(|&(_, stats)| stats)
+    }
+
+    pub fn hostile(&self, a: Race, b: Race) -> bool {
+        self.hostile.contains(&(a, b))
+    }
+
+    fn race(&self, symbol: char) -> Option<Race> {
+        self.races
+            .iter()
+            .map(|&(race, _)| race)
+            .find(|race| race.symbol() == symbol)
+    }
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules::standard()
+    }
+}
+
+#[derive(Clone)]
+pub struct Battle {
+    map: Map,
+    units: Vec<Unit>,
+    rules: Rules,
+    /// living units of each race
+    alive: HashMap<Race, usize>,
+    allow_dead: bool,
+    round: i32,
+    log: Option<Vec<Event>>,
+    /// per unit id
+    tallies: Vec<Tally>,
+}
+
+impl Battle {
+    /// Parse the cave with goblins attacking at 3 and elves at `attack`. If
+    /// `allow_dead` is false the battle counts as lost for the elves as soon
+    /// as one of them dies.
+    pub fn new(s: &str, attack: i32, allow_dead: bool) -> Result<Self> {
+        let rules = Rules::standard().with_race(
+            Race::Elf,
+            Stats {
+                hitpoint: 200,
+                attack,
+            },
+        );
+        Battle::with_rules(s, rules, allow_dead)
+    }
+
+    /// Parse the cave, where every race of `rules` is drawn with its symbol.
+    /// Fails on any other symbol.
+    pub fn with_rules(s: &str, rules: Rules, allow_dead: bool) -> Result<Self> {
+        let mut map = Vec::new();
+        let mut units = vec![];
+        let mut alive = HashMap::new();
+        for (y, row) in s.lines().enumerate() {
+            map.push(Vec::new());
+            for (x, ch) in row.chars().enumerate() {
+                match ch {
+                    '#' => map[y].push(Slot::Wall),
+                    '.' => map[y].push(Slot::Cavern),
+                    _ => {
+                        let race = rules.race(ch).ok_or(AoCError::DirtyInput)?;
+                        let stats = rules.stats(race).ok_or(AoCError::DirtyInput)?;
+                        map[y].push(Slot::Occupied(race));
+                        units.push(Unit::new(units.len(), race, Point(x, y), stats));
+                        *alive.entry(race).or_insert(0) += 1;
+                    }
+                }
+            }
+        }
+
+        let tallies = units
+            .iter()
+            .map(|unit| Tally {
+                race: unit.race,
+                start: unit.loc,
+                dealt: 0,
+                taken: 0,
+                kills: 0,
+                travelled: 0,
+                died: None,
+            })
+            .collect();
+        Ok(Self {
+            map,
+            units,
+            tallies,
+            rules,
+            alive,
+            round: 0,
+            allow_dead,
+            log: None,
+        })
+    }
+
+    /// Change the attack power of every living unit of `race`, typically on
+    /// a clone to see how the battle would go on from here.
+    pub fn set_attack(&mut self, race: Race, attack: i32) {
+        for unit in self.units.iter_mut().filter(|unit| unit.race == race) {
+            unit.attack = attack;
+        }
+    }
+
+    pub fn units(&self) -> &[Unit] {
+        &self.units
+    }
+
+    pub fn rules(&self) -> &Rules {
+        &self.rules
+    }
+
+    /// Record the events of the following rounds.
+    pub fn enable_log(&mut self) {
+        self.log = Some(Vec::new());
+    }
+
+    /// the events since the log was enabled
+    pub fn events(&self) -> Option<&[Event]> {
+        self.log.as_deref()
+    }
+
+    /// what every unit has done so far
+    pub fn report(&self) -> Report {
+        let units = self
+            .tallies
+            .iter()
+            .enumerate()
+            .map(|(id, tally)| {
+                let hitpoint = self
+                    .units
+                    .iter()
+                    .find(|unit| unit.id == id)
+                    .map_or(0, |unit| unit.hitpoint.max(0));
+                UnitReport {
+                    race: tally.race,
+                    start: tally.start,
+                    hitpoint,
+                    damage_dealt: tally.dealt,
+                    damage_taken: tally.taken,
+                    kills: tally.kills,
+                    travelled: tally.travelled,
+                    rounds_survived: tally.died.unwrap_or(self.round),
+                    alive: tally.died.is_none(),
+                }
+            })
+            .collect();
+        Report { units }
+    }
+
+    /// number of full rounds so far
+    pub fn rounds(&self) -> i32 {
+        self.round
+    }
+
+    /// Run rounds until no hostile races are left, or until an elf dies if
+    /// that is not allowed, and return the winner and the outcome. With
+    /// allied survivors the winner is the one first in reading order.
+    pub fn fight(&mut self) -> (Race, i32) {
+        loop {
+            let (race, success, res) = self.round();
+            if success {
+                return (race, res);
+            }
+        }
+    }
+
+    /// Run one round. Returns whether the battle is over, and if so the
+    /// winner and the outcome, the remaining hit points times the number of
+    /// full rounds.
+    pub fn round(&mut self) -> (Race, bool, i32) {
+        let elves = self.alive(Race::Elf);
+
+        // traverse all units and attck-move
+        let mut full_round = true;
+        for index in 0..self.units.len() {
+            if self.units[index].hitpoint <= 0 {
+                continue;
+            }
+            // the battle ends as soon as a unit has no one left to fight,
+            // and the round it happens in does not count
+            if self.over() {
+                full_round = false;
+                break;
+            }
+            if !self.has_enemies(self.units[index].race) {
+                continue;
+            }
+            if !self.attack(index) {
+                let targets = self.targets(self.units[index].race);
+                if let Some(next_loc) = self.unit_move(index, targets) {
+                    self.emit(Event::Moved {
+                        from: self.units[index].loc,
+                        to: next_loc,
+                    });
+                    self.step(index, next_loc);
+                    self.attack(index);
+                }
+            }
+            // no need to play on once the elves have lost
+            if !self.allow_dead && self.alive(Race::Elf) < elves {
+                full_round = false;
+                break;
+            }
+        }
+
+        if full_round {
+            self.round += 1;
+        }
+        self.emit(Event::RoundCompleted { rounds: self.round });
+        self.end_round();
+
+        if !self.allow_dead && elves != self.alive(Race::Elf) {
+            return (Race::Goblin, true, 0);
+        }
+
+        // check end condition
+        if self.over() {
+            let hps = self.units.iter().map(|unit| unit.hitpoint).sum::<i32>();
+            let race = self.units[0].race;
+            return (race, true, hps * self.round);
+        }
+
+        (Race::Elf, false, 0)
+    }
+
+    /// The least attack power above their current one with which the elves
+    /// win without losing anyone, and the outcome then, searching with up to
+    /// `threads` battles at a time.
+    pub fn least_attack(&self, search: Search, threads: usize) -> Option<(i32, i32)> {
+        let current = self
+            .units
+            .iter()
+            .filter(|unit| unit.race == Race::Elf)
+            .map(|unit| unit.attack)
+            .max()
+            .unwrap_or(0);
+        // more than this kills any enemy with one blow
+        let most = self
+            .units
+            .iter()
+            .filter(|unit| self.rules.hostile(Race::Elf, unit.race))
+            .map(|unit| unit.hitpoint)
+            .max()?;
+        let threads = threads.max(1);
+        match search {
+            Search::Linear => {
+                let attacks = (current + 1..=most).collect::<Vec<_>>();
+                let mut lost = vec![];
+                attacks
+                    .chunks(threads)
+                    .find_map(|attacks| self.narrow(attacks, &mut lost))
+            }
+            Search::Bisect => {
+                // the elves lose with everything in `lost` and win with `best`
+                let mut lost = vec![];
+                let mut best = None;
+                let gallop = std::iter::successors(Some(1), |raise| Some(raise * 2))
+                    .map(|raise| current + raise)
+                    .take_while(|&attack| attack < most)
+                    .chain(Some(most))
+                    .collect::<Vec<_>>();
+                for attacks in gallop.chunks(threads) {
+                    best = self.narrow(attacks, &mut lost);
+                    if best.is_some() {
+                        break;
+                    }
+                }
+                while let Some((win, _)) = best {
+                    let lose = lost.iter().copied().filter(|&a| a < win).max();
+                    let lose = lose.unwrap_or(current);
+                    if win - lose <= 1 {
+                        break;
+                    }
+                    // split (lose, win) at up to `threads` evenly spaced points
+                    let gap = win - lose;
+                    let mut probes = (1..=threads as i32)
+                        .map(|k| lose + gap * k / (threads as i32 + 1))
+                        .filter(|&attack| attack > lose)
+                        .collect::<Vec<_>>();
+                    probes.dedup();
+                    best = self.narrow(&probes, &mut lost).or(best);
+                }
+                best
+            }
+        }
+    }
+
+    /// Try the increasing `attacks`, and return the first with which the
+    /// elves win after adding the ones before it to `lost`.
+    fn narrow(&self, attacks: &[i32], lost: &mut Vec<i32>) -> Option<(i32, i32)> {
+        for (&attack, outcome) in attacks.iter().zip(self.flawless_all(attacks)) {
+            match outcome {
+                Some(outcome) => return Some((attack, outcome)),
+                None => lost.push(attack),
+            }
+        }
+        None
+    }
+
+    /// the outcome if the elves survive from here with `attack` and no losses
+    fn flawless(&self, attack: i32) -> Option<i32> {
+        let mut battle = self.clone();
+        battle.allow_dead = false;
+        battle.log = None;
+        battle.set_attack(Race::Elf, attack);
+        let (_, outcome) = battle.fight();
+        (battle.alive(Race::Elf) == self.alive(Race::Elf)).then_some(outcome)
+    }
+
+    /// `flawless` for each attack, in parallel
+    fn flawless_all(&self, attacks: &[i32]) -> Vec<Option<i32>> {
+        std::thread::scope(|scope| {
+            let handles = attacks
+                .iter()
+                .map(|&attack| scope.spawn(move || self.flawless(attack)))
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect()
+        })
+    }
+
+    /// Delete the dead and keep the rest in reading order.
+    fn end_round(&mut self) {
+        self.units.retain(|unit| unit.hitpoint > 0);
+        self.units.sort_unstable_by_key(|unit| unit.loc);
+    }
+
+    fn emit(&mut self, event: Event) {
+        if let Some(log) = self.log.as_mut() {
+            log.push(event);
+        }
+    }
+
+    fn step(&mut self, index: usize, to: Point) {
+        self.set(to, Slot::Occupied(self.units[index].race));
+        self.set(self.units[index].loc, Slot::Cavern);
+        self.units[index].loc = to;
+        self.tallies[self.units[index].id].travelled += 1;
+    }
+
+    fn alive(&self, race: Race) -> usize {
+        self.alive.get(&race).copied().unwrap_or(0)
+    }
+
+    fn has_enemies(&self, race: Race) -> bool {
+        self.alive
+            .iter()
+            .any(|(&other, &count)| count > 0 && self.rules.hostile(race, other))
+    }
+
+    /// whether no living units are hostile to each other
+    fn over(&self) -> bool {
+        !self
+            .alive
+            .iter()
+            .any(|(&race, &count)| count > 0 && self.has_enemies(race))
+    }
+
+    fn set(&mut self, p: Point, slot: Slot) {
+        let Point(x, y) = p;
+        self.map[y][x] = slot;
+    }
+
+    /// the four neighbours of `p` in reading order
+    fn neighbors(p: Point) -> [Point; 4] {
+        // Map is surrounded by walls so we have confidence that
+        // it would not be out-of-bound.
+        let Point(x, y) = p;
+        [
+            Point(x, y - 1),
+            Point(x - 1, y),
+            Point(x + 1, y),
+            Point(x, y + 1),
+        ]
+    }
+
+    fn around(&self, p: Point, slot: Slot) -> Vec<Point> {
+        Battle::neighbors(p)
+            .into_iter()
+            .filter(|&Point(x, y)| self.map[y][x] == slot)
+            .collect()
+    }
+
+    /// Number of steps from `from` to every square reachable through open
+    /// cavern.
+    fn distances(&self, from: Point) -> HashMap<Point, usize> {
+        let mut dist = HashMap::new();
+        let mut queue = VecDeque::new();
+        dist.insert(from, 0);
+        queue.push_back(from);
+        while let Some(node) = queue.pop_front() {
+            let next = dist[&node] + 1;
+            for neighbor in self.around(node, Slot::Cavern) {
+                if let Entry::Vacant(entry) = dist.entry(neighbor) {
+                    entry.insert(next);
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+        dist
+    }
+
+    /// The square the unit steps to: towards the nearest reachable square in
+    /// range of an enemy, the first in reading order on a tie, along the
+    /// shortest path whose first step comes first in reading order.
+    fn unit_move(&self, index: usize, targets: HashSet<Point>) -> Option<Point> {
+        let loc = self.units[index].loc;
+        let dist = self.distances(loc);
+        let (_, chosen) = targets
+            .iter()
+            .filter_map(|target| dist.get(target).map(|&d| (d, *target)))
+            .min()?;
+
+        // every step on a shortest path gets one closer to the chosen square
+        let back = self.distances(chosen);
+        self.around(loc, Slot::Cavern)
+            .into_iter()
+            .filter_map(|step| back.get(&step).map(|&d| (d, step)))
+            .min()
+            .map(|(_, step)| step)
+    }
+
+    fn targets(&self, race: Race) -> HashSet<Point> {
+        let mut targets = HashSet::new();
+
+        for unit in self
+            .units
+            .iter()
+            .filter(|&unit| self.rules.hostile(race, unit.race) && unit.hitpoint > 0)
+        {
+            for target in self.around(unit.loc, Slot::Cavern) {
+                targets.insert(target);
+            }
+        }
+
+        targets
+    }
+
+    fn attack(&mut self, index: usize) -> bool {
+        let (race, attack, loc) = (
+            self.units[index].race,
+            self.units[index].attack,
+            self.units[index].loc,
+        );
+        let target = Battle::neighbors(loc)
+            .into_iter()
+            .filter(|&Point(x, y)| match self.map[y][x] {
+                Slot::Occupied(other) => self.rules.hostile(race, other),
+                _ => false,
+            })
+            .filter_map(|p| self.unit_at(p))
+            .min_by_key(|&target| (self.units[target].hitpoint, self.units[target].loc));
+        match target {
+            Some(target) => {
+                self.emit(Event::Attacked {
+                    from: loc,
+                    to: self.units[target].loc,
+                    damage: attack,
+                });
+                self.hit(index, target, attack);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// the living unit at `p`
+    fn unit_at(&self, p: Point) -> Option<usize> {
+        self.units
+            .iter()
+            .position(|unit| unit.loc == p && unit.hitpoint > 0)
+    }
+
+    fn hit(&mut self, attacker: usize, index: usize, damage: i32) {
+        let unit = &mut self.units[index];
+        let taken = damage.min(unit.hitpoint);
+        unit.hitpoint -= damage;
+        let (id, race, loc, dead) = (unit.id, unit.race, unit.loc, unit.hitpoint <= 0);
+        self.tallies[id].taken += taken;
+        let attacker = self.units[attacker].id;
+        self.tallies[attacker].dealt += taken;
+
+        // update map
+        if dead {
+            *self.alive.get_mut(&race).unwrap() -= 1;
+            self.set(loc, Slot::Cavern);
+            self.tallies[id].died = Some(self.round);
+            self.tallies[attacker].kills += 1;
+            self.emit(Event::Died { at: loc, race });
+        }
+    }
+}
+
+impl Debug for Battle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f)?;
+        if self.round == 0 {
+            writeln!(f, "Initially:")?;
+        } else {
+            writeln!(f, "After {} round:", self.round)?;
+        }
+
+        // before print debug information, caller should promise
+        // that units is sorted.
+        let mut index = 0;
+
+        for row in self.map.iter() {
+            let mut units = Vec::new();
+            let s = row
+                .iter()
+                .map(|slot| match slot {
+                    Slot::Wall => '#',
+                    Slot::Cavern => '.',
+                    Slot::Occupied(race) => {
+                        units.push((race.symbol(), self.units[index].hitpoint));
+                        index += 1;
+                        race.symbol()
+                    }
+                })
+                .collect::<String>();
+            write!(f, "{}", s)?;
+            if !units.is_empty() {
+                write!(f, "   ")?;
+                let mut iter = units.into_iter();
+                let unit = iter.next().unwrap();
+                write!(f, "{}({})", unit.0, unit.1)?;
+                for unit in iter {
+                    write!(f, ", ")?;
+                    write!(f, "{}({})", unit.0, unit.1)?;
+                }
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// How `Battle::least_attack` looks for the least attack power.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Search {
+    /// Every attack power from the current one up, exact.
+    Linear,
+    /// Doubling the raise in attack power until the elves win, then
+    /// narrowing down in between. The elves win with the result and lose
+    /// with one less, but more attack power can make things worse for them,
+    /// so a smaller one may win as well.
+    Bisect,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Slot {
+    Wall,
+    Cavern,
+    Occupied(Race),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
+pub enum Race {
+    Elf,
+    Goblin,
+    /// any other race, drawn as its letter
+    Other(char),
+}
+
+impl fmt::Display for Race {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Race::Other(symbol) => write!(f, "{}", symbol),
+            race => write!(f, "{:?}", race),
+        }
+    }
+}
+
+impl Race {
+    pub fn symbol(self) -> char {
+        match self {
+            Race::Elf => 'E',
+            Race::Goblin => 'G',
+            Race::Other(symbol) => symbol,
+        }
+    }
+}
+
+#[test]
+fn forks_are_independent() {
+    let s = crate::load(15);
+    let outcome = Battle::new(&s, 3, true).unwrap().fight();
+
+    let mut battle = Battle::new(&s, 3, true).unwrap();
+    for _ in 0..10 {
+        battle.round();
+    }
+    let mut fork = battle.clone();
+    fork.set_attack(Race::Elf, 200);
+    assert_eq!(fork.fight().0, Race::Elf);
+
+    assert_eq!(battle.rounds(), 10);
+    assert_eq!(battle.fight(), outcome);
+}
+
+/// least elf attack power and the outcome with it
+#[cfg(test)]
+type Flawless = (i32, i32);
+
+/// The example battles of the puzzle with their outcome, and for those that
+/// have one, the least elf attack power with no elf dying and the outcome
+/// then.
+#[cfg(test)]
+const EXAMPLES: [(&str, i32, Option<Flawless>); 6] = [
+    (
+        "#######
+#.G...#
+#...EG#
+#.#.#G#
+#..G#E#
+#.....#
+#######",
+        27730,
+        Some((15, 4988)),
+    ),
+    (
+        "#######
+#G..#E#
+#E#E.E#
+#G.##.#
+#...#E#
+#...E.#
+#######",
+        36334,
+        None,
+    ),
+    (
+        "#######
+#E..EG#
+#.#G.E#
+#E.##E#
+#G..#.#
+#..E#.#
+#######",
+        39514,
+        Some((4, 31284)),
+    ),
+    (
+        "#######
+#E.G#.#
+#.#G..#
+#G.#.G#
+#G..#.#
+#...E.#
+#######",
+        27755,
+        Some((15, 3478)),
+    ),
+    (
+        "#######
+#.E...#
+#.#..G#
+#.###.#
+#E#G#G#
+#...#G#
+#######",
+        28944,
+        Some((12, 6474)),
+    ),
+    (
+        "#########
+#G......#
+#.E.#...#
+#..##..G#
+#...##..#
+#...#...#
+#.G...G.#
+#.....G.#
+#########",
+        18740,
+        Some((34, 1140)),
+    ),
+];
+
+#[test]
+fn examples() {
+    for (cave, outcome, _) in EXAMPLES.iter() {
+        assert_eq!(
+            Battle::new(cave, 3, true).unwrap().fight().1,
+            *outcome,
+            "{}",
+            cave
+        );
+    }
+}
+
+#[test]
+fn examples_without_losses() {
+    for (cave, _, flawless) in EXAMPLES.iter() {
+        let (attack, outcome) = match flawless {
+            Some(flawless) => *flawless,
+            None => continue,
+        };
+        assert_eq!(
+            Battle::new(cave, attack - 1, false).unwrap().fight().0,
+            Race::Goblin,
+            "{}",
+            cave
+        );
+        assert_eq!(
+            Battle::new(cave, attack, false).unwrap().fight(),
+            (Race::Elf, outcome),
+            "{}",
+            cave
+        );
+    }
+}
+
+#[test]
+fn allied_races() {
+    // the elf is stuck behind its dwarf ally, who fights the goblin alone
+    let dwarf = Race::Other('D');
+    let rules = Rules::standard()
+        .with_race(
+            dwarf,
+            Stats {
+                hitpoint: 300,
+                attack: 10,
+            },
+        )
+        .with_hostility(dwarf, Race::Goblin);
+    let mut battle = Battle::with_rules("#######\n#E.D.G#\n#######", rules.clone(), true).unwrap();
+    assert_eq!(battle.fight(), (Race::Elf, 20 * (200 + 243)));
+    assert_eq!(battle.units().len(), 2);
+
+    // a symbol no race of the rules is drawn with
+    assert!(Battle::with_rules("#######\n#E.D.W#\n#######", rules, true).is_err());
+}
+
+#[test]
+fn least_attack_of_examples() {
+    for (cave, _, flawless) in EXAMPLES.iter() {
+        if flawless.is_none() {
+            continue;
+        }
+        let battle = Battle::new(cave, 3, true).unwrap();
+        for threads in [1, 3] {
+            for search in [Search::Linear, Search::Bisect] {
+                assert_eq!(
+                    battle.least_attack(search, threads),
+                    *flawless,
+                    "{:?} with {} threads\n{}",
+                    search,
+                    threads,
+                    cave
+                );
+            }
+        }
+    }
+
+    // the search starts above the current attack power, even if that wins
+    let (cave, _, _) = EXAMPLES[2];
+    let battle = Battle::new(cave, 4, true).unwrap();
+    assert!(battle.flawless(4).is_some());
+    for search in [Search::Linear, Search::Bisect] {
+        assert!(battle.least_attack(search, 3).unwrap().0 > 4);
+    }
+
+    // bisecting can overshoot, but one less than its result loses
+    let battle = Battle::new(&crate::load(15), 3, false).unwrap();
+    let (linear, _) = battle.least_attack(Search::Linear, 4).unwrap();
+    let (bisected, _) = battle.least_attack(Search::Bisect, 4).unwrap();
+    assert_eq!(linear, 17);
+    assert!(bisected >= linear);
+    assert!(battle.flawless(bisected).is_some());
+    assert!(battle.flawless(bisected - 1).is_none());
+}
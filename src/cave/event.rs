@@ -0,0 +1,130 @@
+//! What happens in a battle, one JSON object per line.
+//!
+//! Units are identified by where they stand when the event happens, which
+//! is unique at any time.
+
+use std::io::BufRead;
+
+use serde::{Deserialize, Serialize};
+
+use super::{Battle, Point, Race, Slot};
+use crate::{AoCError, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Moved {
+        from: Point,
+        to: Point,
+    },
+    Attacked {
+        from: Point,
+        to: Point,
+        damage: i32,
+    },
+    /// follows the attack that killed the unit
+    Died {
+        at: Point,
+        race: Race,
+    },
+    /// The end of a call to `Battle::round`, with the number of full rounds
+    /// so far. It stays the same for the round the battle ends in.
+    RoundCompleted {
+        rounds: i32,
+    },
+}
+
+/// Read events written by `write_json_lines`, skipping blank lines.
+pub fn read_events(r: impl BufRead) -> Result<Vec<Event>> {
+    let mut events = Vec::new();
+    for line in r.lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            events.push(serde_json::from_str(&line)?);
+        }
+    }
+    Ok(events)
+}
+
+impl Battle {
+    /// Apply `event` as if the battle had produced it. Replaying the log of
+    /// a battle on its initial state goes through the same states, so a
+    /// prefix of the log rebuilds any intermediate one.
+    pub fn replay(&mut self, event: &Event) -> Result<()> {
+        let mismatch = || AoCError::Replay(format!("{:?}", event));
+        match *event {
+            Event::Moved { from, to } => {
+                let index = self.unit_at(from).ok_or_else(mismatch)?;
+                if self.map[to.1][to.0] != Slot::Cavern {
+                    return Err(mismatch());
+                }
+                self.emit(event.clone());
+                self.step(index, to);
+            }
+            Event::Attacked { from, to, damage } => {
+                let attacker = self.unit_at(from).ok_or_else(mismatch)?;
+                let target = self.unit_at(to).ok_or_else(mismatch)?;
+                self.emit(event.clone());
+                self.hit(attacker, target, damage);
+            }
+            // `hit` has done it already
+            Event::Died { at, race } => {
+                let dead = self
+                    .units
+                    .iter()
+                    .any(|unit| unit.loc == at && unit.race == race && unit.hitpoint <= 0);
+                if !dead || self.unit_at(at).is_some() {
+                    return Err(mismatch());
+                }
+            }
+            Event::RoundCompleted { rounds } => {
+                self.round = rounds;
+                self.emit(event.clone());
+                self.end_round();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn replay_rebuilds_every_round() {
+    let (cave, _, _) = super::EXAMPLES[5];
+    let mut battle = Battle::new(cave, 3, true).unwrap();
+    battle.enable_log();
+    let initial = battle.clone();
+    let mut states = vec![];
+    loop {
+        let (_, over, _) = battle.round();
+        states.push(battle.clone());
+        if over {
+            break;
+        }
+    }
+
+    let mut json = Vec::new();
+    crate::write_json_lines(battle.events().unwrap(), &mut json).unwrap();
+    let events = read_events(json.as_slice()).unwrap();
+    assert_eq!(events, battle.events().unwrap());
+
+    let mut replayed = initial.clone();
+    let mut states = states.into_iter();
+    for event in events.iter() {
+        replayed.replay(event).unwrap();
+        if let Event::RoundCompleted { .. } = event {
+            let state = states.next().unwrap();
+            assert_eq!(replayed.map, state.map);
+            assert_eq!(replayed.units, state.units);
+            assert_eq!(replayed.rounds(), state.rounds());
+        }
+    }
+    assert!(states.next().is_none());
+
+    // a move onto another unit is rejected
+    let mut replayed = initial;
+    let bad = Event::Moved {
+        from: Point(2, 2),
+        to: Point(1, 1),
+    };
+    assert!(replayed.replay(&bad).is_err());
+}
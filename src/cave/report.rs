@@ -0,0 +1,214 @@
+//! How each unit fared in a battle, and each race in total.
+
+use std::{
+    fmt::{self, Display},
+    io::Write,
+};
+
+use super::{Point, Race};
+use crate::Result;
+
+/// Running totals of one unit, kept by the battle from the start.
+#[derive(Debug, Clone)]
+pub(super) struct Tally {
+    pub(super) race: Race,
+    pub(super) start: Point,
+    pub(super) dealt: i32,
+    pub(super) taken: i32,
+    pub(super) kills: usize,
+    pub(super) travelled: usize,
+    /// full rounds before the one the unit died in
+    pub(super) died: Option<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnitReport {
+    pub race: Race,
+    /// where the unit stood at the start
+    pub start: Point,
+    /// left at the end, 0 for the dead
+    pub hitpoint: i32,
+    /// hit points taken off enemies, not counting blows beyond their last
+    pub damage_dealt: i32,
+    pub damage_taken: i32,
+    pub kills: usize,
+    pub travelled: usize,
+    pub rounds_survived: i32,
+    pub alive: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RaceSummary {
+    pub race: Race,
+    pub units: usize,
+    pub survivors: usize,
+    pub hitpoint: i32,
+    pub damage_dealt: i32,
+    pub damage_taken: i32,
+    pub kills: usize,
+    pub travelled: usize,
+}
+
+/// Returned by `Battle::report`, the units in their initial reading order.
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub units: Vec<UnitReport>,
+}
+
+impl Report {
+    /// totals per race, in order of first appearance
+    pub fn summary(&self) -> Vec<RaceSummary> {
+        let mut summary: Vec<RaceSummary> = Vec::new();
+        for unit in self.units.iter() {
+            let index = match summary.iter().position(|race| race.race == unit.race) {
+                Some(index) => index,
+                None => {
+                    summary.push(RaceSummary {
+                        race: unit.race,
+                        units: 0,
+                        survivors: 0,
+                        hitpoint: 0,
+                        damage_dealt: 0,
+                        damage_taken: 0,
+                        kills: 0,
+                        travelled: 0,
+                    });
+                    summary.len() - 1
+                }
+            };
+            let race = &mut summary[index];
+            race.units += 1;
+            if unit.alive {
+                race.survivors += 1;
+                race.hitpoint += unit.hitpoint;
+            }
+            race.damage_dealt += unit.damage_dealt;
+            race.damage_taken += unit.damage_taken;
+            race.kills += unit.kills;
+            race.travelled += unit.travelled;
+        }
+        summary
+    }
+
+    pub fn write_csv(&self, mut w: impl Write) -> Result<()> {
+        writeln!(
+            w,
+            "race,x,y,hitpoint,damage_dealt,damage_taken,kills,travelled,rounds_survived,alive"
+        )?;
+        for unit in self.units.iter() {
+            writeln!(
+                w,
+                "{},{},{},{},{},{},{},{},{},{}",
+                unit.race,
+                unit.start.0,
+                unit.start.1,
+                unit.hitpoint,
+                unit.damage_dealt,
+                unit.damage_taken,
+                unit.kills,
+                unit.travelled,
+                unit.rounds_survived,
+                unit.alive
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn write_summary_csv(&self, mut w: impl Write) -> Result<()> {
+        writeln!(
+            w,
+            "race,units,survivors,hitpoint,damage_dealt,damage_taken,kills,travelled"
+        )?;
+        for race in self.summary() {
+            writeln!(
+                w,
+                "{},{},{},{},{},{},{},{}",
+                race.race,
+                race.units,
+                race.survivors,
+                race.hitpoint,
+                race.damage_dealt,
+                race.damage_taken,
+                race.kills,
+                race.travelled
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// a table of the units followed by one of the races
+impl Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<8} {:>9} {:>4} {:>6} {:>6} {:>5} {:>9} {:>6}",
+            "race", "start", "hp", "dealt", "taken", "kills", "travelled", "rounds"
+        )?;
+        for unit in self.units.iter() {
+            writeln!(
+                f,
+                "{:<8} {:>9} {:>4} {:>6} {:>6} {:>5} {:>9} {:>6}",
+                unit.race.to_string(),
+                format!("{},{}", unit.start.0, unit.start.1),
+                unit.hitpoint,
+                unit.damage_dealt,
+                unit.damage_taken,
+                unit.kills,
+                unit.travelled,
+                unit.rounds_survived
+            )?;
+        }
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:<8} {:>9} {:>4} {:>6} {:>6} {:>5} {:>9}",
+            "race", "survivors", "hp", "dealt", "taken", "kills", "travelled"
+        )?;
+        for race in self.summary() {
+            writeln!(
+                f,
+                "{:<8} {:>9} {:>4} {:>6} {:>6} {:>5} {:>9}",
+                race.race.to_string(),
+                format!("{}/{}", race.survivors, race.units),
+                race.hitpoint,
+                race.damage_dealt,
+                race.damage_taken,
+                race.kills,
+                race.travelled
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn report_adds_up() {
+    use super::{Battle, EXAMPLES};
+
+    let (cave, outcome, _) = EXAMPLES[0];
+    let mut battle = Battle::new(cave, 3, true).unwrap();
+    battle.fight();
+    let report = battle.report();
+    let summary = report.summary();
+    let (goblins, elves) = (&summary[0], &summary[1]);
+    assert_eq!(
+        (elves.race, elves.units, elves.survivors),
+        (Race::Elf, 2, 0)
+    );
+    assert_eq!((goblins.units, goblins.survivors), (4, 4));
+    assert_eq!(goblins.hitpoint * battle.rounds(), outcome);
+    assert_eq!(elves.damage_dealt, goblins.damage_taken);
+    assert_eq!(goblins.damage_dealt, elves.damage_taken);
+    assert_eq!(elves.damage_taken, 2 * 200);
+    assert_eq!(goblins.kills, 2);
+
+    // the goblin at 5,2 walks to 2,2 after killing its neighbour
+    let unit = &report.units[2];
+    assert_eq!((unit.start, unit.travelled), (Point(5, 2), 3));
+    assert!(report.units.iter().all(|unit| unit.rounds_survived <= 47));
+
+    let mut csv = Vec::new();
+    report.write_csv(&mut csv).unwrap();
+    assert_eq!(String::from_utf8(csv).unwrap().lines().count(), 1 + 6);
+}
@@ -0,0 +1,347 @@
+//! Watch a battle round by round in an ANSI terminal.
+//!
+//! Space plays or pauses, `n` or the right arrow steps forward, `b` or the
+//! left arrow steps back, a round number followed by `g` or enter jumps to
+//! it, and `q` quits.
+
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    process::{Command, Stdio},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+use super::{Battle, Point, Race, Slot};
+use crate::Result;
+
+const RESET: &str = "\x1b[0m";
+const BAR: usize = 10;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Key {
+    Toggle,
+    Step,
+    Back,
+    Jump(usize),
+    Quit,
+}
+
+/// Turns the bytes read from a terminal into keys.
+#[derive(Debug, Default)]
+pub struct Keys {
+    /// digits of a round to jump to
+    round: Option<usize>,
+    /// bytes of an escape sequence so far
+    escape: Vec<u8>,
+}
+
+impl Keys {
+    pub fn feed(&mut self, byte: u8) -> Option<Key> {
+        if !self.escape.is_empty() || byte == 0x1b {
+            self.escape.push(byte);
+            return match self.escape[..] {
+                [0x1b] | [0x1b, b'['] => None,
+                [0x1b, b'[', b'C'] => self.escaped(Some(Key::Step)),
+                [0x1b, b'[', b'D'] => self.escaped(Some(Key::Back)),
+                _ => self.escaped(None),
+            };
+        }
+        match byte {
+            b'0'..=b'9' => {
+                let digit = (byte - b'0') as usize;
+                self.round = Some(self.round.unwrap_or(0) * 10 + digit);
+                None
+            }
+            b'g' | b'\n' | b'\r' => self.round.take().map(Key::Jump),
+            b' ' => Some(Key::Toggle),
+            b'n' | b'l' => Some(Key::Step),
+            b'b' | b'h' => Some(Key::Back),
+            b'q' => Some(Key::Quit),
+            _ => None,
+        }
+    }
+
+    fn escaped(&mut self, key: Option<Key>) -> Option<Key> {
+        self.escape.clear();
+        key
+    }
+}
+
+/// Every state of a battle, from the start to the end.
+pub struct Viewer {
+    frames: Vec<Battle>,
+    pub current: usize,
+    pub playing: bool,
+}
+
+impl Viewer {
+    /// Fight `battle` to the end, keeping a copy after every round.
+    pub fn new(battle: Battle) -> Self {
+        let mut frames = vec![battle];
+        loop {
+            let mut next = frames.last().unwrap().clone();
+            let (_, over, _) = next.round();
+            frames.push(next);
+            if over {
+                break;
+            }
+        }
+        Viewer {
+            frames,
+            current: 0,
+            playing: false,
+        }
+    }
+
+    pub fn frames(&self) -> &[Battle] {
+        &self.frames
+    }
+
+    /// Apply `key`, returns false to quit.
+    pub fn press(&mut self, key: Key) -> bool {
+        match key {
+            Key::Toggle => self.playing = !self.playing,
+            Key::Step => {
+                self.playing = false;
+                self.tick();
+            }
+            Key::Back => {
+                self.playing = false;
+                self.current = self.current.saturating_sub(1);
+            }
+            Key::Jump(round) => self.current = round.min(self.frames.len() - 1),
+            Key::Quit => return false,
+        }
+        true
+    }
+
+    /// Move on one frame, pausing at the end.
+    pub fn tick(&mut self) {
+        if self.current + 1 < self.frames.len() {
+            self.current += 1;
+        }
+        if self.current + 1 == self.frames.len() {
+            self.playing = false;
+        }
+    }
+
+    /// the current frame with a status line, starting from the top left
+    pub fn screen(&self) -> String {
+        let battle = &self.frames[self.current];
+        format!(
+            "\x1b[H\x1b[2J\x1b[1mround {} of {}{}   {}\n\n{}",
+            battle.rounds(),
+            self.frames.last().unwrap().rounds(),
+            RESET,
+            if self.playing { "playing" } else { "paused" },
+            render(battle)
+        )
+    }
+
+    /// Show the battle on the terminal until `q`, advancing every `delay`
+    /// while playing.
+    pub fn run(mut self, delay: Duration) -> Result<()> {
+        let _raw = RawMode::enter()?;
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for byte in io::stdin().lock().bytes() {
+                if byte.map(|byte| tx.send(byte)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut keys = Keys::default();
+        let mut out = io::stdout();
+        loop {
+            write!(out, "{}", self.screen())?;
+            writeln!(
+                out,
+                "\nspace play/pause, n step, b back, <round>g jump, q quit"
+            )?;
+            out.flush()?;
+
+            let byte = if self.playing {
+                match rx.recv_timeout(delay) {
+                    Ok(byte) => byte,
+                    Err(RecvTimeoutError::Timeout) => {
+                        self.tick();
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            } else {
+                match rx.recv() {
+                    Ok(byte) => byte,
+                    Err(_) => break,
+                }
+            };
+            if let Some(key) = keys.feed(byte) {
+                if !self.press(key) {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The map with every race in its colour, and next to each row the units on
+/// it with a bar of the hit points they have left.
+pub fn render(battle: &Battle) -> String {
+    let units = battle
+        .units
+        .iter()
+        .filter(|unit| unit.hitpoint > 0)
+        .map(|unit| (unit.loc, unit))
+        .collect::<HashMap<_, _>>();
+
+    let mut s = String::new();
+    for (y, row) in battle.map.iter().enumerate() {
+        let mut bars = Vec::new();
+        for (x, slot) in row.iter().enumerate() {
+            match slot {
+                Slot::Wall => s.push_str(&format!("\x1b[90m#{}", RESET)),
+                Slot::Cavern => s.push_str(&format!("\x1b[2m.{}", RESET)),
+                Slot::Occupied(race) => {
+                    s.push_str(&format!(
+                        "\x1b[1;{}m{}{}",
+                        colour(*race),
+                        race.symbol(),
+                        RESET
+                    ));
+                    if let Some(unit) = units.get(&Point(x, y)) {
+                        let full = battle
+                            .rules
+                            .stats(unit.race)
+                            .map_or(unit.hitpoint, |stats| stats.hitpoint);
+                        bars.push(format!(
+                            "\x1b[{}m{}{} {}",
+                            colour(*race),
+                            race.symbol(),
+                            RESET,
+                            bar(unit.hitpoint, full)
+                        ));
+                    }
+                }
+            }
+        }
+        if !bars.is_empty() {
+            s.push_str("   ");
+            s.push_str(&bars.join("  "));
+        }
+        s.push('\n');
+    }
+    s
+}
+
+/// ANSI foreground colour of `race`
+fn colour(race: Race) -> u8 {
+    match race {
+        Race::Elf => 32,
+        Race::Goblin => 31,
+        // yellow, blue, magenta or cyan
+        Race::Other(symbol) => 33 + (symbol as u8) % 4,
+    }
+}
+
+/// `hitpoint` out of `full` as a bar that turns from green to red
+fn bar(hitpoint: i32, full: i32) -> String {
+    let filled = if full > 0 {
+        (hitpoint.max(0) as usize * BAR)
+            .div_ceil(full as usize)
+            .min(BAR)
+    } else {
+        0
+    };
+    let colour = match hitpoint * 4 {
+        hp if hp > full * 2 => 32,
+        hp if hp > full => 33,
+        _ => 31,
+    };
+    format!(
+        "\x1b[{}m{}\x1b[90m{}{} {:>3}",
+        colour,
+        "█".repeat(filled),
+        "░".repeat(BAR - filled),
+        RESET,
+        hitpoint
+    )
+}
+
+/// Keys reach the program as they are pressed and are not echoed until
+/// this is dropped.
+struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    fn enter() -> Result<Self> {
+        let saved = stty(&["-g"])?;
+        stty(&["-icanon", "-echo", "min", "1"])?;
+        // hide the cursor
+        print!("\x1b[?25l");
+        Ok(RawMode {
+            saved: saved.trim().to_string(),
+        })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        print!("\x1b[?25h");
+        let _ = io::stdout().flush();
+        let _ = stty(&[self.saved.as_str()]);
+    }
+}
+
+fn stty(args: &[&str]) -> Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        let msg = String::from_utf8_lossy(&output.stderr).into_owned();
+        return Err(io::Error::other(msg).into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[test]
+fn viewer_keys() {
+    let (cave, _, _) = super::EXAMPLES[0];
+    let mut viewer = Viewer::new(Battle::new(cave, 3, true).unwrap());
+    assert_eq!(viewer.frames().last().unwrap().rounds(), 47);
+
+    let mut keys = Keys::default();
+    let pressed = b"  n\x1b[C\x1b[D12g99\nq"
+        .iter()
+        .filter_map(|&byte| keys.feed(byte))
+        .collect::<Vec<_>>();
+    use Key::*;
+    assert_eq!(
+        pressed,
+        [Toggle, Toggle, Step, Step, Back, Jump(12), Jump(99), Quit]
+    );
+
+    for key in pressed[..5].iter() {
+        viewer.press(*key);
+    }
+    assert_eq!((viewer.current, viewer.playing), (1, false));
+    viewer.press(Jump(12));
+    assert_eq!(viewer.frames()[viewer.current].rounds(), 12);
+    viewer.press(Jump(99));
+    assert_eq!(viewer.current, viewer.frames().len() - 1);
+    assert!(!viewer.press(Quit));
+
+    // the goblin at 5,2 is left with 131 hit points of 200
+    let screen = viewer.screen();
+    assert!(screen.contains("round 47 of 47"));
+    assert!(screen.contains(&format!(
+        "\x1b[32m{}\x1b[90m{}\x1b[0m 131",
+        "█".repeat(7),
+        "░".repeat(3)
+    )));
+}
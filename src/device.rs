@@ -0,0 +1,578 @@
+//! The wrist device from day 16, 19 and 21.
+//!
+//! A program is a list of `opcode a b c` lines, optionally preceded by `#ip n`
+//! which binds the instruction pointer to register `n`.
+
+use lazy_static::lazy_static;
+use log::debug;
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    rc::Rc,
+    str::FromStr,
+};
+
+use crate::{AoCError, Result};
+
+pub use asm::assemble;
+pub use checked::VmError;
+pub use compile::Compiled;
+pub use deduce::{
+    deduce, generate, parse_instruction, parse_samples, run, solve, Contradiction, Deduction,
+    Mapping, Sample, Solution,
+};
+pub use history::{History, Snapshot};
+pub use idiom::{Idiom, Operand};
+pub use isa::{InstructionSet, Opcode};
+pub use profile::Profile;
+pub use symbolic::{BinOp, Expr, Outcome, Path, Range};
+
+mod asm;
+mod checked;
+mod compile;
+mod deduce;
+mod history;
+mod idiom;
+mod isa;
+mod profile;
+mod symbolic;
+mod transpile;
+
+lazy_static! {
+    // name to index map
+    pub static ref MAP: HashMap<String, usize> = {
+        let names = "addr, addi, mulr, muli, banr, bani, borr, bori, seti, setr, gtir, gtri, gtrr, eqir, eqri, eqrr";
+        let mut map = HashMap::new();
+
+        for (index, name) in names.split(", ").enumerate() {
+            map.insert(name.to_string(), index);
+        }
+
+        map
+    };
+    // index to name map
+    pub static ref REV: HashMap<usize, String> = {
+        let names = "addr, addi, mulr, muli, banr, bani, borr, bori, seti, setr, gtir, gtri, gtrr, eqir, eqri, eqrr";
+        let mut map = HashMap::new();
+
+        for (index, name) in names.split(", ").enumerate() {
+            map.insert(index, name.to_string());
+        }
+
+        map
+    };
+}
+
+pub const OPS: [OP; 16] = [
+    addr, addi, mulr, muli, banr, bani, borr, bori, seti, setr, gtir, gtri, gtrr, eqir, eqri, eqrr,
+];
+
+pub type OP = fn(&[usize], &mut [usize]);
+
+/// How an opcode reads one of its operands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Reg,
+    Imm,
+    Ignored,
+}
+
+/// modes of operand A and B of the opcode called `name`
+pub fn modes(name: &str) -> (Mode, Mode) {
+    match name {
+        "seti" => (Mode::Imm, Mode::Ignored),
+        "setr" => (Mode::Reg, Mode::Ignored),
+        "gtir" | "eqir" => (Mode::Imm, Mode::Reg),
+        "gtri" | "eqri" => (Mode::Reg, Mode::Imm),
+        _ if name.ends_with('r') => (Mode::Reg, Mode::Reg),
+        _ => (Mode::Reg, Mode::Imm),
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Assembler {
+    pub instructions: Vec<Instruction>,
+    pub origin: Vec<String>, // for debug
+    pub regs: [usize; 6],
+    pub ip: usize,
+    pub profile: Option<Profile>,
+    pub history: Option<History>,
+    /// word width in bits used by `exec_checked`, results wrap around at
+    /// `2^width`; the native width with overflow errors if `None`
+    pub width: Option<u32>,
+    // idiom recognised at each instruction index, if any
+    idioms: Vec<Option<Idiom>>,
+    // opcodes the program was loaded with
+    set: Rc<InstructionSet>,
+}
+
+impl Assembler {
+    pub fn load(s: &str) -> Result<Self> {
+        Assembler::load_with(s, InstructionSet::standard())
+    }
+
+    /// Load a program that may use the additional opcodes of `set`.
+    pub fn load_with(s: &str, set: InstructionSet) -> Result<Self> {
+        let mut assembler = Assembler::default();
+        for origin in s.lines() {
+            let instruction = match origin.split(' ').next().and_then(|name| set.index(name)) {
+                Some(index) if index >= OPS.len() => {
+                    let operands = origin
+                        .split(' ')
+                        .skip(1)
+                        .map(str::parse)
+                        .collect::<std::result::Result<Vec<usize>, _>>()?;
+                    match operands[..] {
+                        [a, b, c] => Instruction::OPCode([index, a, b, c]),
+                        _ => return Err(AoCError::DirtyInput),
+                    }
+                }
+                _ => origin.parse()?,
+            };
+            match instruction {
+                Instruction::IP(reg) => assembler.ip = reg,
+                i => {
+                    assembler.instructions.push(i);
+
+                    // debug setting
+                    assembler.origin.push(origin.to_string())
+                }
+            }
+        }
+
+        assembler.idioms = (0..assembler.instructions.len())
+            .map(|pc| Idiom::detect(&assembler.instructions, assembler.ip, pc))
+            .collect();
+        assembler.set = Rc::new(set);
+
+        Ok(assembler)
+    }
+
+    /// Execute at most `limit` instructions, replacing recognised loops with
+    /// their native equivalent.
+    ///
+    /// A replaced loop counts as all the instructions it stands for, so the
+    /// machine ends in exactly the state that `interpret` would reach.
+    pub fn exec(&mut self, limit: u64) {
+        self.run(limit, true);
+    }
+
+    /// Execute at most `limit` instructions one by one.
+    pub fn interpret(&mut self, limit: u64) {
+        self.run(limit, false);
+    }
+
+    fn run(&mut self, mut limit: u64, accelerate: bool) {
+        let ip = self.ip;
+        while let Some(instruction) = self.instructions.get(self.regs[ip]) {
+            if limit == 0 {
+                break;
+            }
+            if let Some(history) = &mut self.history {
+                history.push(Snapshot {
+                    regs: self.regs,
+                    ip,
+                });
+            }
+            if accelerate {
+                if let Some(Some(idiom)) = self.idioms.get(self.regs[ip]) {
+                    match idiom.apply(ip, &self.regs) {
+                        Some((regs, steps)) if steps <= limit => {
+                            debug!("\n{:?} replaced {} steps", idiom, steps);
+                            if let Some(profile) = &mut self.profile {
+                                profile.record_idiom(self.regs[ip], regs[ip], steps);
+                            }
+                            self.regs = regs;
+                            limit -= steps;
+                            continue;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            let pc = self.regs[ip];
+            match instruction {
+                Instruction::OPCode(i) => {
+                    match OPS.get(i[0]) {
+                        Some(op) => op(i, &mut self.regs),
+                        // a stopping instruction leaves the ip register on itself
+                        None if !self.set.exec(i, &mut self.regs) => break,
+                        None => {}
+                    }
+                    if let Some(profile) = &mut self.profile {
+                        profile.record(pc, i[0], self.regs[ip] + 1);
+                    }
+                    debug!(
+                        "\n{}[{}, {}, {}, {}, {}, {}]",
+                        instruction.pseudo(&self.set),
+                        self.regs[0],
+                        self.regs[1],
+                        self.regs[2],
+                        self.regs[3],
+                        self.regs[4],
+                        self.regs[5]
+                    );
+                }
+                _ => unreachable!(),
+            }
+            self.regs[ip] += 1;
+            limit -= 1;
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Instruction {
+    OPCode([usize; 4]),
+    IP(usize),
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::OPCode(i) => {
+                if let Some(name) = REV.get(&i[0]) {
+                    let start = &name[0..2];
+                    let end = &name[2..];
+                    match end {
+                        "rr" => match start {
+                            "gt" => return writeln!(f, "R{} = R{} > R{}", i[3], i[1], i[2]),
+
+                            "eq" => return writeln!(f, "R{} = R{} == R{}", i[3], i[1], i[2]),
+                            _ => {}
+                        },
+                        "ir" => match start {
+                            "gt" => return writeln!(f, "R{} = {} > R{}", i[3], i[1], i[2]),
+
+                            "eq" => return writeln!(f, "R{} = {} == R{}", i[3], i[1], i[2]),
+                            _ => {}
+                        },
+                        "ri" => match start {
+                            "gt" => return writeln!(f, "R{} = R{} > {}", i[3], i[1], i[2]),
+
+                            "eq" => return writeln!(f, "R{} = R{} == {}", i[3], i[1], i[2]),
+                            _ => {}
+                        },
+                        _ => {}
+                    }
+
+                    let start = &name[0..3];
+                    let end = &name[3..];
+                    match end {
+                        "r" => {
+                            let op = match start {
+                                "add" => '+',
+                                "mul" => '*',
+                                "ban" => '&',
+                                "bor" => '|',
+                                "set" => return writeln!(f, "R{} = R{}", i[3], i[1]),
+                                _ => unreachable!(),
+                            };
+
+                            if i[3] == i[1] {
+                                return writeln!(f, "R{} {}= R{}", i[3], op, i[2]);
+                            } else if i[3] == i[2] {
+                                return writeln!(f, "R{} {}= R{}", i[3], op, i[1]);
+                            } else {
+                                return writeln!(f, "R{} = R{} {} R{}", i[3], i[1], op, i[2]);
+                            }
+                        }
+                        "i" => {
+                            let op = match start {
+                                "add" => '+',
+                                "mul" => '*',
+                                "ban" => '&',
+                                "bor" => '|',
+                                "set" => return writeln!(f, "R{} = {}", i[3], i[1]),
+                                _ => unreachable!(),
+                            };
+
+                            if i[3] == i[1] {
+                                return writeln!(f, "R{} {}= {}", i[3], op, i[2]);
+                            } else if i[3] == i[2] {
+                                return writeln!(f, "R{} {}= {}", i[3], op, i[1]);
+                            } else {
+                                return writeln!(f, "R{} = R{} {} {}", i[3], i[1], op, i[2]);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Instruction::IP(_) => unreachable!(),
+        }
+        match self {
+            Instruction::OPCode(i) => writeln!(f, "R{} = op{}({}, {})", i[3], i[0], i[1], i[2]),
+            Instruction::IP(_) => Err(fmt::Error),
+        }
+    }
+}
+
+impl Instruction {
+    /// The instruction as pseudo-code, naming added opcodes after `set`.
+    fn pseudo(&self, set: &InstructionSet) -> String {
+        match self {
+            Instruction::OPCode(i) if !REV.contains_key(&i[0]) => match set.get(i[0]) {
+                Some(op) => format!("R{} = {}({}, {})\n", i[3], op.name(), i[1], i[2]),
+                None => self.to_string(),
+            },
+            _ => self.to_string(),
+        }
+    }
+}
+
+impl FromStr for Instruction {
+    type Err = AoCError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.starts_with("#ip") {
+            let reg = s.trim_start_matches("#ip ");
+            Ok(Instruction::IP(reg.parse()?))
+        } else {
+            let opcode = s.split(' ');
+            let mut buf = vec![];
+            for elem in opcode {
+                buf.push(elem);
+            }
+
+            if buf.len() != 4 {
+                return Err(AoCError::DirtyInput);
+            }
+
+            if MAP.get(buf[0]).is_none() {
+                return Err(AoCError::DirtyInput);
+            }
+
+            Ok(Instruction::OPCode([
+                *MAP.get(buf[0]).unwrap(),
+                buf[1].parse()?,
+                buf[2].parse()?,
+                buf[3].parse()?,
+            ]))
+        }
+    }
+}
+
+// Implement all instruction
+pub fn addr(instruction: &[usize], register: &mut [usize]) {
+    register[instruction[3]] = register[instruction[1]] + register[instruction[2]];
+}
+
+pub fn addi(instruction: &[usize], register: &mut [usize]) {
+    register[instruction[3]] = register[instruction[1]] + instruction[2];
+}
+
+pub fn mulr(instruction: &[usize], register: &mut [usize]) {
+    register[instruction[3]] = register[instruction[1]] * register[instruction[2]];
+}
+
+pub fn muli(instruction: &[usize], register: &mut [usize]) {
+    register[instruction[3]] = register[instruction[1]] * instruction[2];
+}
+
+pub fn banr(instruction: &[usize], register: &mut [usize]) {
+    register[instruction[3]] = register[instruction[1]] & register[instruction[2]];
+}
+
+pub fn bani(instruction: &[usize], register: &mut [usize]) {
+    register[instruction[3]] = register[instruction[1]] & instruction[2];
+}
+
+pub fn borr(instruction: &[usize], register: &mut [usize]) {
+    register[instruction[3]] = register[instruction[1]] | register[instruction[2]];
+}
+
+pub fn bori(instruction: &[usize], register: &mut [usize]) {
+    register[instruction[3]] = register[instruction[1]] | instruction[2];
+}
+
+pub fn setr(instruction: &[usize], register: &mut [usize]) {
+    register[instruction[3]] = register[instruction[1]];
+}
+
+pub fn seti(instruction: &[usize], register: &mut [usize]) {
+    register[instruction[3]] = instruction[1];
+}
+
+pub fn gtir(instruction: &[usize], register: &mut [usize]) {
+    register[instruction[3]] = if instruction[1] > register[instruction[2]] {
+        1
+    } else {
+        0
+    };
+}
+
+pub fn gtri(instruction: &[usize], register: &mut [usize]) {
+    register[instruction[3]] = if register[instruction[1]] > instruction[2] {
+        1
+    } else {
+        0
+    };
+}
+
+pub fn gtrr(instruction: &[usize], register: &mut [usize]) {
+    register[instruction[3]] = if register[instruction[1]] > register[instruction[2]] {
+        1
+    } else {
+        0
+    };
+}
+
+pub fn eqir(instruction: &[usize], register: &mut [usize]) {
+    register[instruction[3]] = if instruction[1] == register[instruction[2]] {
+        1
+    } else {
+        0
+    };
+}
+
+pub fn eqri(instruction: &[usize], register: &mut [usize]) {
+    register[instruction[3]] = if register[instruction[1]] == instruction[2] {
+        1
+    } else {
+        0
+    };
+}
+
+pub fn eqrr(instruction: &[usize], register: &mut [usize]) {
+    register[instruction[3]] = if register[instruction[1]] == register[instruction[2]] {
+        1
+    } else {
+        0
+    };
+}
+
+/// what opcode `name` writes, spelled out independently of `OPS`
+#[cfg(test)]
+fn reference(name: &str, [_, a, b, _]: [usize; 4], regs: &[usize]) -> usize {
+    let r = |v: usize| regs[v];
+    match name {
+        "addr" => r(a) + r(b),
+        "addi" => r(a) + b,
+        "mulr" => r(a) * r(b),
+        "muli" => r(a) * b,
+        "banr" => r(a) & r(b),
+        "bani" => r(a) & b,
+        "borr" => r(a) | r(b),
+        "bori" => r(a) | b,
+        "setr" => r(a),
+        "seti" => a,
+        "gtir" => (a > r(b)) as usize,
+        "gtri" => (r(a) > b) as usize,
+        "gtrr" => (r(a) > r(b)) as usize,
+        "eqir" => (a == r(b)) as usize,
+        "eqri" => (r(a) == b) as usize,
+        "eqrr" => (r(a) == r(b)) as usize,
+        _ => unreachable!(),
+    }
+}
+
+/// Random registers and an instruction for opcode `op` over them. Values are
+/// mostly as small as the register indices, so that immediates and registers
+/// are easily confused, and never large enough to overflow.
+#[cfg(test)]
+fn random_case(rng: &mut rand::rngs::StdRng, op: usize, len: usize) -> ([usize; 4], Vec<usize>) {
+    use rand::Rng;
+
+    let value = |rng: &mut rand::rngs::StdRng| match rng.gen_range(0..4) {
+        0 => rng.gen_range(0..1 << 31),
+        _ => rng.gen_range(0..len),
+    };
+    let regs = (0..len).map(|_| value(rng)).collect();
+    let instruction = [
+        op,
+        rng.gen_range(0..len),
+        rng.gen_range(0..len),
+        rng.gen_range(0..len),
+    ];
+    (instruction, regs)
+}
+
+#[test]
+fn opcodes_match_reference() {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    let mut rng = StdRng::seed_from_u64(16);
+    // day 16 has four registers, days 19 and 21 six
+    for len in [4, 6] {
+        for (op, f) in OPS.iter().enumerate() {
+            let name = REV[&op].as_str();
+            for _ in 0..1000 {
+                let (i, regs) = random_case(&mut rng, op, len);
+                let mut expected = regs.clone();
+                expected[i[3]] = reference(name, i, &regs);
+                let mut actual = regs.clone();
+                f(&i, &mut actual);
+                assert_eq!(actual, expected, "{} {:?} on {:?}", name, i, regs);
+
+                // an ignored operand may hold anything, even a bad register
+                if modes(name).1 == Mode::Ignored {
+                    let mut other = regs.clone();
+                    f(&[i[0], i[1], rng.gen(), i[3]], &mut other);
+                    assert_eq!(other, expected, "{} ignores operand B", name);
+                }
+            }
+        }
+    }
+
+    // the immediate operand of the mixed comparisons is taken literally
+    let regs = [3, 0, 0, 0];
+    let mut out = regs;
+    gtir(&[10, 1, 0, 3], &mut out);
+    assert_eq!(out[3], 0);
+    eqir(&[13, 3, 0, 3], &mut out);
+    assert_eq!(out[3], 1);
+    eqri(&[14, 0, 0, 3], &mut out);
+    assert_eq!(out[3], 0);
+    gtri(&[11, 0, 2, 3], &mut out);
+    assert_eq!(out[3], 1);
+}
+
+#[test]
+fn engines_match_reference() {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    let mut rng = StdRng::seed_from_u64(19);
+    for op in 0..16 {
+        let name = REV[&op].as_str();
+        for _ in 0..100 {
+            let (i, mut regs) = random_case(&mut rng, op, 6);
+            // a single instruction with the ip bound to R5 runs once from 0
+            regs[5] = 0;
+            let mut expected = regs.clone();
+            expected[i[3]] = reference(name, i, &regs);
+            expected[5] += 1;
+
+            let source = format!("#ip 5\n{} {} {} {}", name, i[1], i[2], i[3]);
+            let mut assembler = Assembler::load(&source).unwrap();
+            assembler.regs.copy_from_slice(&regs);
+            let start = assembler.regs;
+
+            assembler.interpret(10);
+            assert_eq!(assembler.regs[..], expected[..], "interpret {}", source);
+
+            assembler.regs = start;
+            assembler.exec(10);
+            assert_eq!(assembler.regs[..], expected[..], "exec {}", source);
+
+            assembler.regs = start;
+            assert_eq!(assembler.exec_checked(10), Ok(1));
+            assert_eq!(assembler.regs[..], expected[..], "checked {}", source);
+
+            let mut compiled = start;
+            assembler.compile().exec(&mut compiled, 10);
+            assert_eq!(compiled[..], expected[..], "compiled {}", source);
+
+            assembler.regs = start;
+            let paths = assembler.explore(&[], 10);
+            let expected = expected.into_iter().map(Expr::Const).collect();
+            assert_eq!(
+                paths[0].outcome,
+                Outcome::Halt { regs: expected },
+                "symbolic {}",
+                source
+            );
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! A small assembler for writing device programs by hand.
+//!
+//! On top of the plain `opcode a b c` and `#ip n` lines it understands
+//! ```text
+//! ; comments, to the end of a line
+//! .reg pc 4            ; name a register
+//! .const MASK 0xffffff ; name a value
+//! #ip pc
+//! loop:                ; a label is the index of the next instruction
+//!     bani r3 MASK r3  ; registers can also be written as `rN`
+//!     jmp loop         ; `seti` to the ip register, one before `loop`
+//! ```
+//! Labels can also be used as plain values, e.g. `seti loop 0 r1`. Labels
+//! and names share one namespace, and none may look like a register.
+
+use std::collections::HashMap;
+
+use super::{Assembler, MAP};
+use crate::{AoCError, Result};
+
+impl Assembler {
+    /// Assemble `source` and load the resulting program.
+    pub fn assemble(source: &str) -> Result<Self> {
+        Assembler::load(&assemble(source)?)
+    }
+}
+
+/// Translate `source` into a standard program.
+pub fn assemble(source: &str) -> Result<String> {
+    let mut names = HashMap::new();
+    let mut labels = HashMap::new();
+    let mut ip = None;
+    // (line number, opcode, operands) of each instruction
+    let mut body: Vec<(usize, &str, Vec<&str>)> = vec![];
+
+    for (idx, line) in source.lines().enumerate() {
+        let line_no = idx + 1;
+        let err = |msg: &str| AoCError::Asm {
+            line: line_no,
+            msg: msg.to_string(),
+        };
+
+        let mut line = line.split(';').next().unwrap().trim();
+        if let Some((label, rest)) = line.split_once(':') {
+            let label = label.trim();
+            if !is_name(label) {
+                return Err(err("bad label"));
+            }
+            if names.contains_key(label) || labels.insert(label, body.len()).is_some() {
+                return Err(err("duplicate label"));
+            }
+            line = rest.trim();
+        }
+
+        let mut words = line.split_whitespace();
+        let head = match words.next() {
+            Some(head) => head,
+            None => continue,
+        };
+        let rest = words.collect::<Vec<_>>();
+        match head {
+            "#ip" => match rest[..] {
+                [reg] => ip = Some((line_no, reg)),
+                _ => return Err(err("expected `#ip register`")),
+            },
+            ".reg" | ".const" => match rest[..] {
+                [name, value] if is_name(name) => {
+                    let value = if head == ".reg" {
+                        register(value)
+                    } else {
+                        number(value)
+                    };
+                    let value = value.ok_or_else(|| err("bad value"))?;
+                    if head == ".reg" && value >= 6 {
+                        return Err(err("no such register"));
+                    }
+                    if labels.contains_key(name) || names.insert(name, value).is_some() {
+                        return Err(err("duplicate name"));
+                    }
+                }
+                _ => return Err(err("expected a name and a value")),
+            },
+            "jmp" => match rest[..] {
+                [target] => body.push((line_no, head, vec![target])),
+                _ => return Err(err("expected `jmp label`")),
+            },
+            op if MAP.contains_key(op) => match rest[..] {
+                [_, _, _] => body.push((line_no, op, rest)),
+                _ => return Err(err("expected three operands")),
+            },
+            _ => return Err(err("unknown instruction")),
+        }
+    }
+
+    let resolve = |line: usize, word: &str| -> Result<usize> {
+        let err = |msg: String| AoCError::Asm { line, msg };
+        if let Some(value) = number(word) {
+            return Ok(value);
+        }
+        if let Some(&value) = names.get(word).or_else(|| labels.get(word)) {
+            return Ok(value);
+        }
+        register(word).ok_or_else(|| err(format!("unknown name `{}`", word)))
+    };
+
+    let mut out = String::new();
+    let ip = match ip {
+        Some((line, reg)) => {
+            let reg = resolve(line, reg)?;
+            out.push_str(&format!("#ip {}\n", reg));
+            Some(reg)
+        }
+        None => None,
+    };
+
+    for (line, op, operands) in body {
+        if op == "jmp" {
+            let ip = ip.ok_or(AoCError::Asm {
+                line,
+                msg: "`jmp` needs `#ip`".to_string(),
+            })?;
+            // the ip register is incremented after the jump
+            let target = resolve(line, operands[0])?
+                .checked_sub(1)
+                .ok_or(AoCError::Asm {
+                    line,
+                    msg: "cannot jump to the first instruction".to_string(),
+                })?;
+            out.push_str(&format!("seti {} 0 {}\n", target, ip));
+        } else {
+            let operands = operands
+                .into_iter()
+                .map(|word| resolve(line, word))
+                .collect::<Result<Vec<_>>>()?;
+            out.push_str(&format!(
+                "{} {} {} {}\n",
+                op, operands[0], operands[1], operands[2]
+            ));
+        }
+    }
+
+    Ok(out)
+}
+
+/// an identifier that cannot be mistaken for a register such as `r2`
+fn is_name(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && register(s).is_none()
+}
+
+fn number(s: &str) -> Option<usize> {
+    match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// a register written as `rN` or just `N`
+fn register(s: &str) -> Option<usize> {
+    number(s.strip_prefix(|c| c == 'r' || c == 'R').unwrap_or(s))
+}
+
+#[test]
+fn assemble_labels_and_names() {
+    use super::Idiom;
+
+    // the inner loop of day 21, written by hand
+    let source = "
+        .reg pc 4
+        .reg q r1
+        .reg t 2
+        .reg n 3
+        .const BASE 0x100
+        #ip pc
+            seti 1000 0 n
+            seti 0 0 q      ; q = 0
+        loop:
+            addi q 1 t
+            muli t BASE t
+            gtrr t n t
+            addr t pc pc
+            addi pc 1 pc
+            jmp done
+            addi q 1 q
+            jmp loop
+        done: setr q 0 0
+    ";
+    let program = assemble(source).unwrap();
+    assert!(program.starts_with("#ip 4\nseti 1000 0 3\nseti 0 0 1\naddi 1 1 2\n"));
+    assert!(program.contains("seti 9 0 4\naddi 1 1 1\nseti 1 0 4\n"));
+
+    let mut assembler = Assembler::assemble(source).unwrap();
+    assert!(matches!(assembler.idioms[2], Some(Idiom::Division { .. })));
+    assembler.interpret(u64::MAX);
+    assert_eq!(assembler.regs[0], 1000 / 256);
+
+    // a standard program assembles to itself
+    let s = crate::load(19);
+    assert_eq!(assemble(&s).unwrap().trim_end(), s.trim_end());
+
+    assert!(matches!(
+        assemble("#ip 0\nstart: jmp start"),
+        Err(AoCError::Asm { line: 2, .. })
+    ));
+    assert!(matches!(
+        assemble("addi r0 1 nowhere"),
+        Err(AoCError::Asm { line: 1, .. })
+    ));
+
+    // names must not shadow registers or each other
+    assert!(matches!(
+        assemble("#ip 5\nseti 1 0 r2\nr2: addi r2 1 r2"),
+        Err(AoCError::Asm { line: 3, .. })
+    ));
+    assert!(matches!(
+        assemble(".reg R0 1"),
+        Err(AoCError::Asm { line: 1, .. })
+    ));
+    assert!(matches!(
+        assemble(".const loop 9\n#ip 5\nloop: jmp loop"),
+        Err(AoCError::Asm { line: 3, .. })
+    ));
+    assert!(matches!(
+        assemble("#ip 5\nloop: jmp loop\n.const loop 9"),
+        Err(AoCError::Asm { line: 3, .. })
+    ));
+}
@@ -0,0 +1,175 @@
+//! Execution that reports malformed programs instead of panicking.
+
+use failure::Fail;
+
+use super::{Assembler, Instruction, InstructionSet, Mode, REV};
+
+#[derive(Fail, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmError {
+    #[fail(
+        display = "instruction {} uses register {} which does not exist",
+        pc, reg
+    )]
+    BadRegister { pc: usize, reg: usize },
+    #[fail(display = "instruction {} overflows", pc)]
+    Overflow { pc: usize },
+    #[fail(display = "no halt after {} steps", steps)]
+    StepLimit { steps: u64 },
+    #[fail(display = "the instruction pointer is bound to register {}", reg)]
+    IpOutOfRange { reg: usize },
+}
+
+impl Assembler {
+    /// Execute until the program halts and return the number of executed
+    /// instructions, or fail once `limit` instructions have run.
+    ///
+    /// Arithmetic happens in `self.width` bits. No idioms are applied.
+    pub fn exec_checked(&mut self, limit: u64) -> Result<u64, VmError> {
+        let ip = self.ip;
+        if ip >= self.regs.len() {
+            return Err(VmError::IpOutOfRange { reg: ip });
+        }
+
+        let mut steps = 0;
+        while let Some(instruction) = self.instructions.get(self.regs[ip]) {
+            if steps == limit {
+                return Err(VmError::StepLimit { steps });
+            }
+
+            let pc = self.regs[ip];
+            let running = match instruction {
+                Instruction::OPCode(i) => step(i, &mut self.regs, &self.set, self.width, pc)?,
+                Instruction::IP(_) => unreachable!(),
+            };
+            if !running {
+                break;
+            }
+            self.regs[ip] =
+                wrap(self.regs[ip] as u128 + 1, self.width).ok_or(VmError::Overflow { pc })?;
+            steps += 1;
+        }
+
+        Ok(steps)
+    }
+}
+
+/// Execute `i`, returning false if it stops the machine.
+fn step(
+    i: &[usize; 4],
+    regs: &mut [usize; 6],
+    set: &InstructionSet,
+    width: Option<u32>,
+    pc: usize,
+) -> Result<bool, VmError> {
+    let op = set.get(i[0]).unwrap();
+    let read = |mode: Mode, v: usize| match mode {
+        Mode::Reg => regs
+            .get(v)
+            .map(|&r| r as u128)
+            .ok_or(VmError::BadRegister { pc, reg: v }),
+        Mode::Imm => Ok(v as u128),
+        Mode::Ignored => Ok(0),
+    };
+    let (ma, mb) = op.modes();
+    let (a, b) = (read(ma, i[1])?, read(mb, i[2])?);
+
+    let name = match REV.get(&i[0]) {
+        Some(name) => name.as_str(),
+        None => {
+            let value = match op.eval(a as usize, b as usize) {
+                Some(value) => value,
+                None => return Ok(false),
+            };
+            return write(i, regs, wrap(value as u128, width), pc);
+        }
+    };
+    let value = match &name[..2] {
+        "ad" => a + b,
+        "mu" => a * b,
+        "ba" => a & b,
+        "bo" => a | b,
+        "se" => a,
+        "gt" => (a > b) as u128,
+        "eq" => (a == b) as u128,
+        _ => unreachable!(),
+    };
+
+    write(i, regs, wrap(value, width), pc)
+}
+
+/// store the result of `i`, which is `None` if it overflowed
+fn write(
+    i: &[usize; 4],
+    regs: &mut [usize; 6],
+    value: Option<usize>,
+    pc: usize,
+) -> Result<bool, VmError> {
+    let value = value.ok_or(VmError::Overflow { pc })?;
+    let c = regs
+        .get_mut(i[3])
+        .ok_or(VmError::BadRegister { pc, reg: i[3] })?;
+    *c = value;
+    Ok(true)
+}
+
+/// reduce `value` to `width` bits, or check that it fits in a `usize`
+fn wrap(value: u128, width: Option<u32>) -> Option<usize> {
+    match width {
+        Some(width) => {
+            let mask = (1u128 << width.min(usize::BITS)) - 1;
+            Some((value & mask) as usize)
+        }
+        None => usize::try_from(value).ok(),
+    }
+}
+
+#[test]
+fn exec_zero_steps() {
+    let mut assembler = Assembler::load(&crate::load(19)).unwrap();
+    assembler.exec(0);
+    assembler.interpret(0);
+    assembler.compile().exec(&mut assembler.regs, 0);
+    assert_eq!(assembler.regs, [0; 6]);
+    assert_eq!(
+        assembler.exec_checked(0),
+        Err(VmError::StepLimit { steps: 0 })
+    );
+}
+
+#[test]
+fn checked_errors() {
+    let mut assembler = Assembler::load("#ip 0\nseti 5 0 1\naddr 1 7 2").unwrap();
+    assert_eq!(
+        assembler.exec_checked(10),
+        Err(VmError::BadRegister { pc: 1, reg: 7 })
+    );
+
+    let mut assembler = Assembler::load("seti 9 0 1\nmuli 1 3 1\nseti 9 0 0").unwrap();
+    assembler.regs[1] = usize::MAX;
+    assembler.regs[0] = 1;
+    assert_eq!(assembler.exec_checked(10), Err(VmError::Overflow { pc: 1 }));
+
+    let mut assembler = Assembler::load("#ip 6\nseti 0 0 0").unwrap();
+    assert_eq!(
+        assembler.exec_checked(10),
+        Err(VmError::IpOutOfRange { reg: 6 })
+    );
+}
+
+#[test]
+fn checked_wraparound() {
+    let mut assembler = Assembler::load("#ip 5\naddi 0 1 0\nmuli 1 65899 1").unwrap();
+    assembler.width = Some(24);
+    assembler.regs[0] = 16777215;
+    assembler.regs[1] = 16777215;
+    assert_eq!(assembler.exec_checked(10), Ok(2));
+    assert_eq!(assembler.regs[0], 0);
+    assert_eq!(assembler.regs[1], (16777215 * 65899) & 16777215);
+
+    // the day 21 program masks every value to 24 bits itself
+    let mut wrapped = Assembler::load(&crate::load(21)).unwrap();
+    let mut native = Assembler::load(&crate::load(21)).unwrap();
+    wrapped.width = Some(24);
+    assert_eq!(wrapped.exec_checked(100_000), native.exec_checked(100_000));
+    assert_eq!(wrapped.regs, native.regs);
+}
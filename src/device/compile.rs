@@ -0,0 +1,162 @@
+//! Compilation of a loaded program into a vector of specialised closures.
+//!
+//! Each closure executes one instruction and returns the index of the next
+//! one, or `None` if the machine stops. The instruction pointer lives in a
+//! local variable while running: reads of the `#ip` register are replaced
+//! by the constant index of the instruction and writes to it turn into
+//! jumps, so the register itself is only written back when execution stops.
+
+use std::rc::Rc;
+
+use super::{modes, Assembler, Instruction, InstructionSet, Mode, REV};
+
+type Regs = [usize; 6];
+type Step = Box<dyn Fn(&mut Regs) -> Option<usize>>;
+
+pub struct Compiled {
+    steps: Vec<Step>,
+    ip: usize,
+}
+
+#[derive(Clone, Copy)]
+enum Src {
+    Reg(usize),
+    Imm(usize),
+}
+
+impl Assembler {
+    /// Compile the program into threaded code.
+    pub fn compile(&self) -> Compiled {
+        let steps = self
+            .instructions
+            .iter()
+            .enumerate()
+            .map(|(pc, instruction)| compile(instruction, &self.set, self.ip, pc))
+            .collect();
+
+        Compiled { steps, ip: self.ip }
+    }
+}
+
+impl Compiled {
+    /// Execute at most `limit` instructions on `regs`, which has the same
+    /// effect as `Assembler::interpret` with these registers.
+    pub fn exec(&self, regs: &mut Regs, mut limit: u64) {
+        let mut pc = regs[self.ip];
+        while let Some(step) = self.steps.get(pc) {
+            if limit == 0 {
+                break;
+            }
+            match step(regs) {
+                Some(next) => pc = next,
+                None => break,
+            }
+            limit -= 1;
+        }
+        regs[self.ip] = pc;
+    }
+}
+
+fn compile(instruction: &Instruction, set: &Rc<InstructionSet>, ip: usize, pc: usize) -> Step {
+    let i = match instruction {
+        Instruction::OPCode(i) => *i,
+        Instruction::IP(_) => unreachable!(),
+    };
+    let name = match REV.get(&i[0]) {
+        Some(name) => name.as_str(),
+        // an added opcode goes through the instruction set
+        None => {
+            let set = Rc::clone(set);
+            return Box::new(move |r: &mut Regs| {
+                r[ip] = pc;
+                set.exec(&i, r).then(|| r[ip] + 1)
+            });
+        }
+    };
+
+    // the value of the ip register is known while this instruction runs
+    let reg = |r: usize| if r == ip { Src::Imm(pc) } else { Src::Reg(r) };
+    let src = |mode: Mode, v: usize| match mode {
+        Mode::Reg => reg(v),
+        Mode::Imm => Src::Imm(v),
+        Mode::Ignored => Src::Imm(0),
+    };
+    let (ma, mb) = modes(name);
+    let (a, b) = (src(ma, i[1]), src(mb, i[2]));
+
+    match &name[..2] {
+        "ad" => step(|x, y| x + y, a, b, i[3], ip, pc),
+        "mu" => step(|x, y| x * y, a, b, i[3], ip, pc),
+        "ba" => step(|x, y| x & y, a, b, i[3], ip, pc),
+        "bo" => step(|x, y| x | y, a, b, i[3], ip, pc),
+        "se" => step(|x, _| x, a, b, i[3], ip, pc),
+        "gt" => step(|x, y| (x > y) as usize, a, b, i[3], ip, pc),
+        "eq" => step(|x, y| (x == y) as usize, a, b, i[3], ip, pc),
+        _ => unreachable!(),
+    }
+}
+
+fn step<F>(f: F, a: Src, b: Src, c: usize, ip: usize, pc: usize) -> Step
+where
+    F: Fn(usize, usize) -> usize + 'static,
+{
+    use Src::*;
+
+    if c == ip {
+        // a write to the ip register is a jump
+        return match (a, b) {
+            (Reg(a), Reg(b)) => Box::new(move |r: &mut Regs| Some(f(r[a], r[b]) + 1)),
+            (Reg(a), Imm(b)) => Box::new(move |r: &mut Regs| Some(f(r[a], b) + 1)),
+            (Imm(a), Reg(b)) => Box::new(move |r: &mut Regs| Some(f(a, r[b]) + 1)),
+            (Imm(a), Imm(b)) => {
+                let next = Some(f(a, b) + 1);
+                Box::new(move |_: &mut Regs| next)
+            }
+        };
+    }
+
+    let next = Some(pc + 1);
+    match (a, b) {
+        (Reg(a), Reg(b)) => Box::new(move |r: &mut Regs| {
+            r[c] = f(r[a], r[b]);
+            next
+        }),
+        (Reg(a), Imm(b)) => Box::new(move |r: &mut Regs| {
+            r[c] = f(r[a], b);
+            next
+        }),
+        (Imm(a), Reg(b)) => Box::new(move |r: &mut Regs| {
+            r[c] = f(a, r[b]);
+            next
+        }),
+        (Imm(a), Imm(b)) => {
+            let value = f(a, b);
+            Box::new(move |r: &mut Regs| {
+                r[c] = value;
+                next
+            })
+        }
+    }
+}
+
+#[test]
+fn compiled_matches_interpretation() {
+    for (day, r0, limit) in [(19, 0, u64::MAX), (21, 0, 100_000), (21, 1, 12_345)] {
+        let mut assembler = Assembler::load(&crate::load(day)).unwrap();
+        let compiled = assembler.compile();
+        let mut regs = [r0, 0, 0, 0, 0, 0];
+        compiled.exec(&mut regs, limit);
+
+        assembler.regs[0] = r0;
+        assembler.interpret(limit);
+        assert_eq!(regs, assembler.regs, "day {}", day);
+    }
+
+    // a jump to the last index there is leaves the machine, no sentinel
+    let mut assembler = Assembler::load("#ip 1\nseti 18446744073709551614 0 1").unwrap();
+    let mut regs = [0; 6];
+    assembler.compile().exec(&mut regs, 10);
+    assembler.interpret(10);
+    assert_eq!(regs[1], usize::MAX);
+    assert_eq!(regs, assembler.regs);
+}
@@ -0,0 +1,415 @@
+//! Deduction of opcode numbers from `Before/After` samples, as in day 16.
+
+use failure::Fail;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::{modes, Mode, OPS, REV};
+
+const BEFORE_PREFIX: &str = "Before: [";
+const AFTER_PREFIX: &str = "After:  [";
+
+/// A mapping from opcode number to index into `OPS`.
+pub type Mapping = [usize; 16];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    pub before: [usize; 4],
+    pub instruction: [usize; 4],
+    pub after: [usize; 4],
+}
+
+impl Sample {
+    /// bit set of the `OPS` that turn `before` into `after`
+    pub fn candidates(&self) -> u16 {
+        let mut set = 0;
+        for (index, op) in OPS.iter().enumerate() {
+            if !self.valid(index) {
+                continue;
+            }
+            let mut register = self.before;
+            op(&self.instruction, &mut register);
+            if register == self.after {
+                set |= 1 << index;
+            }
+        }
+        set
+    }
+
+    // all registers named by the instruction exist for `OPS[index]`
+    fn valid(&self, index: usize) -> bool {
+        let (a, b) = modes(REV[&index].as_str());
+        let reg = |mode: Mode, v: usize| mode != Mode::Reg || v < self.before.len();
+        reg(a, self.instruction[1])
+            && reg(b, self.instruction[2])
+            && reg(Mode::Reg, self.instruction[3])
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Deduction {
+    Unique(Mapping),
+    /// Every consistent mapping, or only the first `limit` of them if
+    /// `complete` is false.
+    Ambiguous {
+        mappings: Vec<Mapping>,
+        complete: bool,
+    },
+}
+
+/// The samples, by index, which cannot all hold at once.
+#[derive(Fail, Debug, Clone, PartialEq, Eq)]
+#[fail(display = "samples {:?} contradict each other", samples)]
+pub struct Contradiction {
+    pub samples: Vec<usize>,
+}
+
+/// Find the mappings consistent with all samples, enumerating at most
+/// `limit` of them when there is more than one.
+pub fn deduce(samples: &[Sample], limit: usize) -> Result<Deduction, Contradiction> {
+    // candidates of each opcode number and the samples that narrowed them
+    let mut candidates = [u16::MAX; 16];
+    let mut narrowed: Vec<Vec<usize>> = vec![vec![]; 16];
+    for (idx, sample) in samples.iter().enumerate() {
+        let number = sample.instruction[0];
+        if number >= 16 {
+            return Err(Contradiction { samples: vec![idx] });
+        }
+        let set = sample.candidates();
+        if set == 0 {
+            return Err(Contradiction { samples: vec![idx] });
+        }
+        if candidates[number] & set != candidates[number] {
+            candidates[number] &= set;
+            narrowed[number].push(idx);
+        }
+        if candidates[number] == 0 {
+            return Err(Contradiction {
+                samples: narrowed[number].clone(),
+            });
+        }
+    }
+
+    if let Some(numbers) = hall_violation(&candidates) {
+        let mut samples = numbers
+            .into_iter()
+            .flat_map(|number| narrowed[number].iter().copied())
+            .collect::<Vec<_>>();
+        samples.sort_unstable();
+        return Err(Contradiction { samples });
+    }
+
+    let mut mappings = vec![];
+    let mut mapping = [0; 16];
+    let complete = enumerate(&candidates, 0, &mut mapping, &mut mappings, limit.max(1));
+    if complete && mappings.len() == 1 {
+        Ok(Deduction::Unique(mappings[0]))
+    } else {
+        Ok(Deduction::Ambiguous { mappings, complete })
+    }
+}
+
+/// A set of opcode numbers that together have fewer candidates than
+/// members, if any, found through a maximum bipartite matching.
+fn hall_violation(candidates: &[u16; 16]) -> Option<Vec<usize>> {
+    // op index -> opcode number
+    let mut owner = [None; 16];
+    for number in 0..16 {
+        let mut seen = 0u16;
+        if !augment(candidates, number, &mut seen, &mut owner) {
+            // everything reachable from an unmatched number by alternating
+            // paths only has the ops in `seen` to share
+            let mut numbers = vec![number];
+            for (op, owner) in owner.iter().enumerate() {
+                if seen & 1 << op != 0 {
+                    numbers.extend(*owner);
+                }
+            }
+            numbers.sort_unstable();
+            return Some(numbers);
+        }
+    }
+    None
+}
+
+fn augment(
+    candidates: &[u16; 16],
+    number: usize,
+    seen: &mut u16,
+    owner: &mut [Option<usize>; 16],
+) -> bool {
+    for op in 0..16 {
+        if candidates[number] & 1 << op == 0 || *seen & 1 << op != 0 {
+            continue;
+        }
+        *seen |= 1 << op;
+        let free = match owner[op] {
+            None => true,
+            Some(other) => augment(candidates, other, seen, owner),
+        };
+        if free {
+            owner[op] = Some(number);
+            return true;
+        }
+    }
+    false
+}
+
+/// Collect the perfect matchings extending the first `number` entries of
+/// `mapping`. Returns false if it stopped at `limit`.
+fn enumerate(
+    candidates: &[u16; 16],
+    number: usize,
+    mapping: &mut Mapping,
+    out: &mut Vec<Mapping>,
+    limit: usize,
+) -> bool {
+    if number == 16 {
+        if out.len() == limit {
+            return false;
+        }
+        out.push(*mapping);
+        return true;
+    }
+
+    let used = mapping[..number]
+        .iter()
+        .fold(0u16, |set, &op| set | 1 << op);
+    let mut free = candidates[number] & !used;
+    while free != 0 {
+        let op = free.trailing_zeros() as usize;
+        free &= free - 1;
+        mapping[number] = op;
+        if !enumerate(candidates, number + 1, mapping, out, limit) {
+            return false;
+        }
+    }
+    true
+}
+
+/// The answers of day 16 for one input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Solution {
+    /// samples that behave like three or more opcodes
+    pub ambiguous: usize,
+    pub deduction: Result<Deduction, Contradiction>,
+    /// registers after running the test program, if the samples pin down
+    /// the opcodes
+    pub registers: Option<[usize; 4]>,
+}
+
+/// Deduce the opcodes from the samples of a day 16 input, enumerating at
+/// most `limit` mappings, and run its test program with them.
+pub fn solve(s: &str, limit: usize) -> Solution {
+    let mut s = s.split("\n\n\n");
+    let samples = parse_samples(s.next().unwrap_or(""));
+    let ambiguous = samples
+        .iter()
+        .filter(|sample| sample.candidates().count_ones() >= 3)
+        .count();
+    let deduction = deduce(&samples, limit);
+    let registers = match &deduction {
+        Ok(Deduction::Unique(mapping)) => Some(run(s.next().unwrap_or(""), mapping)),
+        _ => None,
+    };
+    Solution {
+        ambiguous,
+        deduction,
+        registers,
+    }
+}
+
+/// Run the test program of a day 16 input from all zeros.
+pub fn run(program: &str, mapping: &Mapping) -> [usize; 4] {
+    let mut register = [0; 4];
+    for line in program.lines().filter(|line| !line.is_empty()) {
+        let instruction = parse_instruction(line);
+        OPS[mapping[instruction[0]]](&instruction, &mut register);
+    }
+    register
+}
+
+/// Produce an input in the day 16 format where opcode number `n` means
+/// `OPS[permutation[n]]`, along with the registers after running its test
+/// program from all zeros.
+pub fn generate(
+    permutation: &Mapping,
+    seed: u64,
+    samples: usize,
+    length: usize,
+) -> (String, [usize; 4]) {
+    // keeps the test program clear of overflows
+    const BOUND: usize = 1 << 32;
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let random_instruction = |rng: &mut StdRng| {
+        [
+            rng.gen_range(0..16),
+            rng.gen_range(0..4),
+            rng.gen_range(0..4),
+            rng.gen_range(0..4),
+        ]
+    };
+
+    let mut input = vec![];
+    for _ in 0..samples {
+        let instruction = random_instruction(&mut rng);
+        let mut before = [0; 4];
+        for reg in before.iter_mut() {
+            *reg = rng.gen_range(0..4);
+        }
+        let mut after = before;
+        OPS[permutation[instruction[0]]](&instruction, &mut after);
+
+        input.push(format!(
+            "{}{}]\n{}\n{}{}]",
+            BEFORE_PREFIX,
+            join(&before, ", "),
+            join(&instruction, " "),
+            AFTER_PREFIX,
+            join(&after, ", ")
+        ));
+    }
+    let mut input = input.join("\n\n");
+    input.push_str("\n\n\n\n");
+
+    let mut register = [0; 4];
+    let mut program = vec![];
+    while program.len() < length {
+        let instruction = random_instruction(&mut rng);
+        let index = permutation[instruction[0]];
+        let (a, b) = modes(REV[&index].as_str());
+        let value = |mode: Mode, v: usize| match mode {
+            Mode::Reg => register[v],
+            Mode::Imm => v,
+            Mode::Ignored => 0,
+        };
+        let (a, b) = (value(a, instruction[1]), value(b, instruction[2]));
+        if a.saturating_mul(b) >= BOUND || a + b >= BOUND {
+            continue;
+        }
+
+        OPS[index](&instruction, &mut register);
+        program.push(join(&instruction, " "));
+    }
+    input.push_str(&program.join("\n"));
+
+    (input, register)
+}
+
+fn join(values: &[usize], sep: &str) -> String {
+    values
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join(sep)
+}
+
+/// Parse the first section of the day 16 input.
+pub fn parse_samples(s: &str) -> Vec<Sample> {
+    let lines = s
+        .lines()
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>();
+    lines
+        .chunks(3)
+        .map(|chunk| Sample {
+            before: parse_register(chunk[0], BEFORE_PREFIX),
+            instruction: parse_instruction(chunk[1]),
+            after: parse_register(chunk[2], AFTER_PREFIX),
+        })
+        .collect()
+}
+
+fn parse_register(s: &str, prefix: &str) -> [usize; 4] {
+    let mut register = [0; 4];
+    for (reg, value) in register
+        .iter_mut()
+        .zip(s[prefix.len()..s.len() - 1].split(", "))
+    {
+        *reg = value.parse().unwrap();
+    }
+    register
+}
+
+pub fn parse_instruction(s: &str) -> [usize; 4] {
+    let mut instruction = [0; 4];
+    for (field, value) in instruction.iter_mut().zip(s.split(' ')) {
+        *field = value.parse().unwrap();
+    }
+    instruction
+}
+
+#[test]
+fn deduce_opcodes() {
+    let s = crate::load(16);
+    let samples = parse_samples(s.split("\n\n\n").next().unwrap());
+    let map = match deduce(&samples, 2) {
+        Ok(Deduction::Unique(map)) => map,
+        other => panic!("{:?}", other),
+    };
+    for sample in samples.iter() {
+        assert!(sample.candidates() & 1 << map[sample.instruction[0]] != 0);
+    }
+
+    match deduce(&samples[..5], 3) {
+        Ok(Deduction::Ambiguous { mappings, complete }) => {
+            assert_eq!(mappings.len(), 3);
+            assert!(!complete);
+        }
+        other => panic!("{:?}", other),
+    }
+
+    // a sample no opcode explains
+    let mut bad = samples[..20].to_vec();
+    bad[7].after = [usize::MAX; 4];
+    assert_eq!(deduce(&bad, 1), Err(Contradiction { samples: vec![7] }));
+
+    // two opcode numbers that can only be the same opcode
+    let single = *samples
+        .iter()
+        .find(|sample| sample.candidates().count_ones() == 1)
+        .unwrap();
+    let mut twin = single;
+    twin.instruction[0] = (single.instruction[0] + 1) % 16;
+    assert_eq!(
+        deduce(&[single, twin], 1),
+        Err(Contradiction {
+            samples: vec![0, 1]
+        })
+    );
+}
+
+#[test]
+fn generated_samples_round_trip() {
+    use super::OP;
+    use rand::seq::SliceRandom;
+
+    let mut rng = StdRng::seed_from_u64(16);
+    for seed in 0..10 {
+        let mut permutation = [0; 16];
+        for (idx, op) in permutation.iter_mut().enumerate() {
+            *op = idx;
+        }
+        permutation.shuffle(&mut rng);
+
+        let (input, expect) = generate(&permutation, seed, 800, 1000);
+        let samples = parse_samples(input.split("\n\n\n").next().unwrap());
+        assert_eq!(samples.len(), 800);
+        let ambiguous = samples
+            .iter()
+            .filter(|sample| {
+                let behaves_like = |op: &&OP| {
+                    let mut register = sample.before;
+                    op(&sample.instruction, &mut register);
+                    register == sample.after
+                };
+                OPS.iter().filter(behaves_like).count() >= 3
+            })
+            .count();
+
+        let solution = solve(&input, 2);
+        assert_eq!(solution.ambiguous, ambiguous);
+        assert_eq!(solution.deduction, Ok(Deduction::Unique(permutation)));
+        assert_eq!(solution.registers, Some(expect));
+    }
+}
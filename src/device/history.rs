@@ -0,0 +1,150 @@
+//! Snapshots of the machine and a bounded record of past states for
+//! stepping backwards.
+
+use std::collections::VecDeque;
+
+use super::Assembler;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Snapshot {
+    pub regs: [usize; 6],
+    pub ip: usize,
+}
+
+impl Snapshot {
+    /// index of the next instruction to execute
+    pub fn pc(&self) -> usize {
+        self.regs[self.ip]
+    }
+}
+
+/// The states before each of the last `capacity` executed instructions,
+/// oldest first.
+#[derive(Debug, Clone)]
+pub struct History {
+    capacity: usize,
+    states: VecDeque<Snapshot>,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        History {
+            capacity,
+            states: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub(super) fn push(&mut self, snapshot: Snapshot) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.states.len() == self.capacity {
+            self.states.pop_front();
+        }
+        self.states.push_back(snapshot);
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Snapshot> {
+        self.states.iter()
+    }
+}
+
+impl Assembler {
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            regs: self.regs,
+            ip: self.ip,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.regs = snapshot.regs;
+        self.ip = snapshot.ip;
+    }
+
+    /// Remember the state before each of the next executed instructions, up
+    /// to `capacity` of them. A loop replaced by an idiom counts as a single
+    /// instruction.
+    pub fn enable_history(&mut self, capacity: usize) {
+        self.history = Some(History::new(capacity));
+    }
+
+    /// Undo up to `n` instructions and return how many were undone.
+    pub fn step_back(&mut self, n: usize) -> usize {
+        let history = match &mut self.history {
+            Some(history) => history,
+            None => return 0,
+        };
+
+        let n = n.min(history.states.len());
+        let mut state = None;
+        for _ in 0..n {
+            state = history.states.pop_back();
+        }
+        if let Some(state) = state {
+            self.restore(&state);
+        }
+        n
+    }
+
+    /// Rewind to just before the last recorded instruction that changed
+    /// register `reg`, so that stepping forward once repeats the change.
+    ///
+    /// Returns the number of instructions undone, or `None` without
+    /// touching the machine if `reg` does not exist or no recorded
+    /// instruction changed it.
+    pub fn reverse_until_changed(&mut self, reg: usize) -> Option<usize> {
+        let history = self.history.as_ref()?;
+        let current = *self.regs.get(reg)?;
+        let back = history
+            .states
+            .iter()
+            .rev()
+            .position(|state| state.regs[reg] != current)?;
+        Some(self.step_back(back + 1))
+    }
+}
+
+#[test]
+fn step_back_and_rewind() {
+    let s = crate::load(21);
+    let mut assembler = Assembler::load(&s).unwrap();
+    assembler.enable_history(100);
+    assembler.interpret(1000);
+    let snapshot = assembler.snapshot();
+
+    assembler.interpret(50);
+    assert_eq!(assembler.step_back(50), 50);
+    assert_eq!(assembler.snapshot(), snapshot);
+
+    let mut expect = Assembler::load(&s).unwrap();
+    expect.interpret(1000 - 30);
+    assert_eq!(assembler.step_back(30), 30);
+    assert_eq!(assembler.regs, expect.regs);
+
+    // only 100 states are kept, 80 of which were just undone
+    assert_eq!(assembler.step_back(usize::MAX), 20);
+    assert_eq!(assembler.step_back(1), 0);
+
+    // R5 is only written by the outer loop of the program
+    assembler.enable_history(1000);
+    assembler.exec(1000);
+    let r5 = assembler.regs[5];
+    let back = assembler.reverse_until_changed(5).unwrap();
+    assert!(back > 0);
+    assert_ne!(assembler.regs[5], r5);
+    assembler.exec(1);
+    assert_eq!(assembler.regs[5], r5);
+
+    let regs = assembler.regs;
+    assert_eq!(assembler.reverse_until_changed(6), None);
+    assert_eq!(assembler.regs, regs);
+}
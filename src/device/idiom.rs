@@ -0,0 +1,395 @@
+//! Recognition of hot loops that have a cheap native equivalent.
+//!
+//! Every idiom is matched at the head of its loop. The loop's behaviour from
+//! the head only depends on the registers it reads, so the idiom can be
+//! applied whenever the instruction pointer reaches the head, no matter how
+//! control got there.
+
+use super::{Instruction, REV};
+
+const REGS: usize = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Reg(usize),
+    Imm(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Idiom {
+    /// The inner loop of day 19:
+    /// ```text
+    /// loop
+    ///     t = a * b
+    ///     if t == n
+    ///         acc += a
+    ///     b += 1
+    ///     if b > n
+    ///         break
+    /// ```
+    DivisorScan {
+        a: usize,
+        b: usize,
+        n: usize,
+        t: usize,
+        acc: usize,
+        exit: usize,
+    },
+    /// The whole double loop of day 19, which adds every divisor of `n`
+    /// that is not below `a` to `acc`:
+    /// ```text
+    /// loop
+    ///     b = 1
+    ///     DivisorScan
+    ///     a += 1
+    ///     if a > n
+    ///         break
+    /// ```
+    DivisorSum {
+        a: usize,
+        b: usize,
+        n: usize,
+        t: usize,
+        acc: usize,
+        exit: usize,
+    },
+    /// Division by counting up, as in day 21:
+    /// ```text
+    /// loop
+    ///     t = (q + 1) * d
+    ///     if t > n
+    ///         break
+    ///     q += 1
+    /// ```
+    Division {
+        q: usize,
+        d: Operand,
+        n: usize,
+        t: usize,
+        exit: usize,
+    },
+}
+
+impl Idiom {
+    /// Find the idiom whose loop head is at `pc`, if there is one.
+    pub fn detect(program: &[Instruction], ip: usize, pc: usize) -> Option<Idiom> {
+        divisor_sum(program, ip, pc)
+            .or_else(|| divisor_scan(program, ip, pc))
+            .or_else(|| division(program, ip, pc))
+    }
+
+    /// registers whose values the loop depends on
+    pub fn reads(&self) -> Vec<usize> {
+        match *self {
+            Idiom::DivisorScan { a, b, n, acc, .. } => vec![a, b, n, acc],
+            Idiom::DivisorSum { a, n, acc, .. } => vec![a, n, acc],
+            Idiom::Division { q, d, n, .. } => match d {
+                Operand::Reg(d) => vec![q, n, d],
+                Operand::Imm(_) => vec![q, n],
+            },
+        }
+    }
+
+    /// registers the loop changes, besides the ip register
+    pub fn writes(&self) -> Vec<usize> {
+        match *self {
+            Idiom::DivisorScan { b, t, acc, .. } => vec![b, t, acc],
+            Idiom::DivisorSum { a, b, t, acc, .. } => vec![a, b, t, acc],
+            Idiom::Division { q, t, .. } => vec![q, t],
+        }
+    }
+
+    /// Compute the registers after the loop has run to completion, along
+    /// with the number of instructions the plain interpretation would take.
+    ///
+    /// Returns `None` when the loop would not terminate or the registers are
+    /// outside of what the native version covers; the caller should fall
+    /// back to interpretation then.
+    pub fn apply(&self, ip: usize, regs: &[usize; REGS]) -> Option<([usize; REGS], u64)> {
+        let mut out = *regs;
+        let steps = match *self {
+            Idiom::DivisorScan {
+                a,
+                b,
+                n,
+                t,
+                acc,
+                exit,
+            } => {
+                let (x, k, n_) = (regs[a], regs[b], regs[n]);
+                if x == 0 {
+                    return None;
+                }
+                let last = k.max(n_);
+                if n_ % x == 0 && (k..=last).contains(&(n_ / x)) {
+                    out[acc] += x;
+                }
+                out[b] = last + 1;
+                out[t] = 1;
+                out[ip] = exit;
+
+                // every iteration takes 8 steps, the last one skips the jump back
+                8 * (last - k + 1) as u64 - 1
+            }
+            Idiom::DivisorSum {
+                a,
+                b,
+                n,
+                t,
+                acc,
+                exit,
+            } => {
+                let (x, n_) = (regs[a], regs[n]);
+                if x == 0 {
+                    return None;
+                }
+                out[acc] += divisors(n_).into_iter().filter(|&d| d >= x).sum::<usize>();
+
+                let last = x.max(n_);
+                let inner = n_.max(1);
+                out[a] = last + 1;
+                out[b] = inner + 1;
+                out[t] = 1;
+                out[ip] = exit;
+
+                // `b = 1`, the inner scan and 4 steps of bookkeeping per
+                // iteration, the last one skips the jump back
+                let scan = 8 * inner as u64 - 1;
+                (last - x + 1) as u64 * (1 + scan + 4) - 1
+            }
+            Idiom::Division { q, d, n, t, exit } => {
+                let d = match d {
+                    Operand::Reg(r) => regs[r],
+                    Operand::Imm(v) => v,
+                };
+                if d == 0 {
+                    return None;
+                }
+                let q0 = regs[q];
+                let q1 = q0.max(regs[n] / d);
+                out[q] = q1;
+                out[t] = 1;
+                out[ip] = exit;
+
+                // 7 steps for every increment of q, 5 for the final test
+                7 * (q1 - q0) as u64 + 5
+            }
+        };
+
+        Some((out, steps))
+    }
+}
+
+/// all divisors of `n` in no particular order
+pub(super) fn divisors(n: usize) -> Vec<usize> {
+    let mut divisors = vec![];
+    let mut i = 1;
+    while i * i <= n {
+        if n.is_multiple_of(i) {
+            divisors.push(i);
+            if i * i != n {
+                divisors.push(n / i);
+            }
+        }
+        i += 1;
+    }
+    divisors
+}
+
+fn decode(instruction: &Instruction) -> Option<(&str, usize, usize, usize)> {
+    match instruction {
+        Instruction::OPCode(i) => REV.get(&i[0]).map(|name| (name.as_str(), i[1], i[2], i[3])),
+        Instruction::IP(_) => None,
+    }
+}
+
+/// the other element of a commutative operand pair
+fn other((x, y): (usize, usize), known: usize) -> Option<usize> {
+    if x == known {
+        Some(y)
+    } else if y == known {
+        Some(x)
+    } else {
+        None
+    }
+}
+
+/// all registers are valid and no two of them alias
+fn distinct(regs: &[usize]) -> bool {
+    regs.iter()
+        .enumerate()
+        .all(|(idx, &r)| r < REGS && !regs[..idx].contains(&r))
+}
+
+/// `ip += t` in either operand order
+fn is_skip(instruction: Option<(&str, usize, usize, usize)>, t: usize, ip: usize) -> bool {
+    matches!(instruction, Some(("addr", x, y, c)) if c == ip && other((x, y), ip) == Some(t))
+}
+
+fn divisor_scan(program: &[Instruction], ip: usize, l: usize) -> Option<Idiom> {
+    let get = |k: usize| program.get(l + k).and_then(decode);
+
+    let (m1, m2, t) = match get(0)? {
+        ("mulr", x, y, t) => (x, y, t),
+        _ => return None,
+    };
+    let n = match get(1)? {
+        ("eqrr", x, y, c) if c == t => other((x, y), t)?,
+        _ => return None,
+    };
+    if !is_skip(get(2), t, ip) || get(3)? != ("addi", ip, 1, ip) {
+        return None;
+    }
+    let b = match get(5)? {
+        ("addi", x, 1, c) if x == c => x,
+        _ => return None,
+    };
+    let a = other((m1, m2), b)?;
+    let acc = match get(4)? {
+        ("addr", x, y, c) if other((x, y), c) == Some(a) => c,
+        _ => return None,
+    };
+    if get(6)? != ("gtrr", b, n, t) || !is_skip(get(7), t, ip) {
+        return None;
+    }
+    match get(8)? {
+        ("seti", target, _, c) if c == ip && target + 1 == l => {}
+        _ => return None,
+    }
+
+    if !distinct(&[a, b, n, t, acc, ip]) {
+        return None;
+    }
+
+    Some(Idiom::DivisorScan {
+        a,
+        b,
+        n,
+        t,
+        acc,
+        exit: l + 9,
+    })
+}
+
+fn divisor_sum(program: &[Instruction], ip: usize, l: usize) -> Option<Idiom> {
+    let get = |k: usize| program.get(l + k).and_then(decode);
+
+    let (a, b, n, t, acc) = match divisor_scan(program, ip, l + 1)? {
+        Idiom::DivisorScan {
+            a, b, n, t, acc, ..
+        } => (a, b, n, t, acc),
+        _ => unreachable!(),
+    };
+    match get(0)? {
+        ("seti", 1, _, c) if c == b => {}
+        _ => return None,
+    }
+    if get(10)? != ("addi", a, 1, a) || get(11)? != ("gtrr", a, n, t) || !is_skip(get(12), t, ip) {
+        return None;
+    }
+    match get(13)? {
+        ("seti", target, _, c) if c == ip && target + 1 == l => {}
+        _ => return None,
+    }
+
+    Some(Idiom::DivisorSum {
+        a,
+        b,
+        n,
+        t,
+        acc,
+        exit: l + 14,
+    })
+}
+
+fn division(program: &[Instruction], ip: usize, l: usize) -> Option<Idiom> {
+    let get = |k: usize| program.get(l + k).and_then(decode);
+
+    let (q, t) = match get(0)? {
+        ("addi", q, 1, t) => (q, t),
+        _ => return None,
+    };
+    let d = match get(1)? {
+        ("muli", x, d, c) if x == t && c == t => Operand::Imm(d),
+        ("mulr", x, y, c) if c == t => Operand::Reg(other((x, y), t)?),
+        _ => return None,
+    };
+    let n = match get(2)? {
+        ("gtrr", x, n, c) if x == t && c == t => n,
+        _ => return None,
+    };
+    if !is_skip(get(3), t, ip) || get(4)? != ("addi", ip, 1, ip) {
+        return None;
+    }
+    let exit = match get(5)? {
+        ("seti", target, _, c) if c == ip => target + 1,
+        _ => return None,
+    };
+    if get(6)? != ("addi", q, 1, q) {
+        return None;
+    }
+    match get(7)? {
+        ("seti", target, _, c) if c == ip && target + 1 == l => {}
+        _ => return None,
+    }
+
+    let distinct = match d {
+        Operand::Reg(d) => distinct(&[q, t, n, d, ip]),
+        Operand::Imm(_) => distinct(&[q, t, n, ip]),
+    };
+    if !distinct {
+        return None;
+    }
+
+    Some(Idiom::Division { q, d, n, t, exit })
+}
+
+#[test]
+fn idioms_are_recognised() {
+    use super::Assembler;
+
+    let d19 = Assembler::load(&crate::load(19)).unwrap();
+    assert!(matches!(
+        d19.idioms[2],
+        Some(Idiom::DivisorSum { exit: 16, .. })
+    ));
+    assert!(matches!(
+        d19.idioms[3],
+        Some(Idiom::DivisorScan { exit: 12, .. })
+    ));
+
+    let d21 = Assembler::load(&crate::load(21)).unwrap();
+    assert!(matches!(
+        d21.idioms[18],
+        Some(Idiom::Division { exit: 26, .. })
+    ));
+    assert_eq!(d21.idioms.iter().flatten().count(), 1);
+}
+
+#[test]
+fn idioms_match_interpretation() {
+    use super::Assembler;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    let mut rng = StdRng::seed_from_u64(19);
+    let cases = [(19, 2, 0..40), (19, 3, 0..40), (21, 18, 0..100_000)];
+    for (day, head, range) in cases {
+        let s = crate::load(day);
+        for _ in 0..200 {
+            let mut regs = [0; 6];
+            for reg in regs.iter_mut() {
+                *reg = rng.gen_range(range.clone());
+            }
+            let limit = rng.gen_range(1..20_000);
+
+            let mut fast = Assembler::load(&s).unwrap();
+            let mut slow = Assembler::load(&s).unwrap();
+            regs[fast.ip] = head;
+            fast.regs = regs;
+            slow.regs = regs;
+            fast.exec(limit);
+            slow.interpret(limit);
+            assert_eq!(fast.regs, slow.regs, "day {} from {:?}", day, regs);
+        }
+    }
+}
@@ -0,0 +1,303 @@
+//! Instruction sets that extend the sixteen opcodes of the puzzle.
+//!
+//! A set always starts with the standard opcodes at their usual indices, so
+//! idioms, compilation and the other engines keep working on the standard
+//! part of a program. Added opcodes get the indices after them.
+
+use std::fmt;
+
+use super::{modes, Mode, OPS, REV};
+use crate::{AoCError, Result};
+
+/// An instruction of the device: `C = op(A, B)`.
+pub trait Opcode {
+    fn name(&self) -> &str;
+
+    /// how operands A and B are read
+    fn modes(&self) -> (Mode, Mode);
+
+    /// number of operands read, register C is always the destination
+    fn arity(&self) -> usize {
+        let (a, b) = self.modes();
+        [a, b].iter().filter(|&&mode| mode != Mode::Ignored).count()
+    }
+
+    /// the value written to register C, or `None` to stop the machine
+    fn eval(&self, a: usize, b: usize) -> Option<usize>;
+
+    /// A Rust statement with the same effect, given the operands and the
+    /// destination as Rust expressions. The machine is stopped by `break`.
+    fn rust(&self, _a: &str, _b: &str, _c: &str) -> Option<String> {
+        None
+    }
+}
+
+/// `OPS[index]`
+struct Standard(usize);
+
+impl Opcode for Standard {
+    fn name(&self) -> &str {
+        REV[&self.0].as_str()
+    }
+
+    fn modes(&self) -> (Mode, Mode) {
+        modes(self.name())
+    }
+
+    fn eval(&self, a: usize, b: usize) -> Option<usize> {
+        Some(match &self.name()[..2] {
+            "ad" => a + b,
+            "mu" => a * b,
+            "ba" => a & b,
+            "bo" => a | b,
+            "se" => a,
+            "gt" => (a > b) as usize,
+            "eq" => (a == b) as usize,
+            _ => unreachable!(),
+        })
+    }
+
+    fn rust(&self, a: &str, b: &str, c: &str) -> Option<String> {
+        let value = match &self.name()[..2] {
+            "ad" => format!("{} + {}", a, b),
+            "mu" => format!("{} * {}", a, b),
+            "ba" => format!("{} & {}", a, b),
+            "bo" => format!("{} | {}", a, b),
+            "se" => a.to_string(),
+            "gt" => format!("({} > {}) as usize", a, b),
+            "eq" => format!("({} == {}) as usize", a, b),
+            _ => unreachable!(),
+        };
+        Some(format!("{} = {}", c, value))
+    }
+}
+
+/// Division and remainder in the register and immediate forms, which stop
+/// the machine when dividing by zero.
+struct Divide {
+    name: &'static str,
+    b: Mode,
+    remainder: bool,
+}
+
+impl Opcode for Divide {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn modes(&self) -> (Mode, Mode) {
+        (Mode::Reg, self.b)
+    }
+
+    fn eval(&self, a: usize, b: usize) -> Option<usize> {
+        if self.remainder {
+            a.checked_rem(b)
+        } else {
+            a.checked_div(b)
+        }
+    }
+
+    fn rust(&self, a: &str, b: &str, c: &str) -> Option<String> {
+        let op = if self.remainder { '%' } else { '/' };
+        Some(format!(
+            "{} = if {} == 0 {{ break }} else {{ {} {} {} }}",
+            c, b, a, op, b
+        ))
+    }
+}
+
+/// Stops the machine with the instruction pointer on itself.
+struct Halt;
+
+impl Opcode for Halt {
+    fn name(&self) -> &str {
+        "halt"
+    }
+
+    fn modes(&self) -> (Mode, Mode) {
+        (Mode::Ignored, Mode::Ignored)
+    }
+
+    fn eval(&self, _: usize, _: usize) -> Option<usize> {
+        None
+    }
+
+    fn rust(&self, _: &str, _: &str, _: &str) -> Option<String> {
+        Some("break".to_string())
+    }
+}
+
+pub struct InstructionSet {
+    ops: Vec<Box<dyn Opcode>>,
+}
+
+impl InstructionSet {
+    /// the sixteen opcodes of the puzzle
+    pub fn standard() -> Self {
+        InstructionSet {
+            ops: (0..OPS.len())
+                .map(|index| Box::new(Standard(index)) as Box<dyn Opcode>)
+                .collect(),
+        }
+    }
+
+    /// the standard set with `divr`, `divi`, `modr`, `modi` and `halt`
+    pub fn experimental() -> Self {
+        let divide = |name, b, remainder| Divide { name, b, remainder };
+        InstructionSet::standard()
+            .with(divide("divr", Mode::Reg, false))
+            .and_then(|set| set.with(divide("divi", Mode::Imm, false)))
+            .and_then(|set| set.with(divide("modr", Mode::Reg, true)))
+            .and_then(|set| set.with(divide("modi", Mode::Imm, true)))
+            .and_then(|set| set.with(Halt))
+            .unwrap()
+    }
+
+    /// Add `op` with the next free index.
+    pub fn with(mut self, op: impl Opcode + 'static) -> Result<Self> {
+        if self.index(op.name()).is_some() {
+            return Err(AoCError::DuplicateOpcode(op.name().to_string()));
+        }
+        self.ops.push(Box::new(op));
+        Ok(self)
+    }
+
+    pub fn index(&self, name: &str) -> Option<usize> {
+        self.ops.iter().position(|op| op.name() == name)
+    }
+
+    pub fn get(&self, index: usize) -> Option<&dyn Opcode> {
+        self.ops.get(index).map(|op| op.as_ref())
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Execute `i` on `regs`. Returns false if the machine stops instead.
+    pub fn exec(&self, i: &[usize; 4], regs: &mut [usize]) -> bool {
+        let op = &self.ops[i[0]];
+        let operand = |mode: Mode, v: usize| match mode {
+            Mode::Reg => regs[v],
+            Mode::Imm => v,
+            Mode::Ignored => 0,
+        };
+        let (ma, mb) = op.modes();
+        match op.eval(operand(ma, i[1]), operand(mb, i[2])) {
+            Some(value) => {
+                regs[i[3]] = value;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Default for InstructionSet {
+    fn default() -> Self {
+        InstructionSet::standard()
+    }
+}
+
+impl fmt::Debug for InstructionSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.ops.iter().map(|op| op.name()))
+            .finish()
+    }
+}
+
+#[test]
+fn added_opcodes() {
+    use super::{Assembler, Expr, Outcome};
+
+    // sum of the divisors of R3, stopping with `halt` before R0 is cleared
+    let source = "#ip 5
+seti 1 0 1
+modr 3 1 2
+eqri 2 0 2
+mulr 2 1 2
+addr 0 2 0
+addi 1 1 1
+gtrr 1 3 4
+addr 5 4 5
+seti 0 0 5
+halt 0 0 0
+seti 0 0 0";
+    assert!(Assembler::load(source).is_err());
+    let mut assembler = Assembler::load_with(source, InstructionSet::experimental()).unwrap();
+    let start = [0, 0, 0, 920, 0, 0];
+    let expected = [2160, 921, 920, 920, 1, 9];
+
+    assembler.regs = start;
+    assembler.interpret(u64::MAX);
+    assert_eq!(assembler.regs, expected);
+
+    assembler.regs = start;
+    assembler.exec(u64::MAX);
+    assert_eq!(assembler.regs, expected);
+
+    assembler.regs = start;
+    assert!(assembler.exec_checked(100_000).is_ok());
+    assert_eq!(assembler.regs, expected);
+
+    let mut regs = start;
+    assembler.compile().exec(&mut regs, u64::MAX);
+    assert_eq!(regs, expected);
+
+    assembler.regs = start;
+    assembler.enable_profiling();
+    assembler.interpret(u64::MAX);
+    let report = assembler.profile_report(20).unwrap();
+    assert!(report.contains("R2 = modr(3, 1)"));
+    assert!(!report.contains("op16"));
+
+    assembler.regs = start;
+    let paths = assembler.explore(&[], 100_000);
+    let expected_regs = expected.iter().map(|&v| Expr::Const(v)).collect();
+    assert_eq!(
+        paths[0].outcome,
+        Outcome::Halt {
+            regs: expected_regs
+        }
+    );
+
+    let source = assembler.transpile("run").unwrap();
+    assert!(source.contains("9 => break,"));
+    assert!(source.contains("r2 = if r1 == 0 { break } else { r3 % r1 }"));
+
+    // dividing by zero stops the machine on the division
+    let mut assembler =
+        Assembler::load_with("#ip 5\ndivr 0 1 2", InstructionSet::experimental()).unwrap();
+    assembler.exec(10);
+    assert_eq!(assembler.regs, [0; 6]);
+
+    // a user supplied opcode
+    struct Square(&'static str);
+    impl Opcode for Square {
+        fn name(&self) -> &str {
+            self.0
+        }
+        fn modes(&self) -> (Mode, Mode) {
+            (Mode::Reg, Mode::Ignored)
+        }
+        fn eval(&self, a: usize, _: usize) -> Option<usize> {
+            Some(a * a)
+        }
+    }
+    assert!(InstructionSet::standard().with(Square("addr")).is_err());
+    let set = InstructionSet::standard().with(Square("sqr")).unwrap();
+    assert_eq!(set.get(16).unwrap().arity(), 1);
+    let mut assembler = Assembler::load_with("#ip 5\nsqr 0 0 1\nsqr 1 0 1", set).unwrap();
+    assembler.regs[0] = 3;
+    assembler.interpret(10);
+    assert_eq!(assembler.regs[1], 81);
+    assert!(matches!(
+        assembler.transpile("run"),
+        Err(AoCError::NoRustForm(name)) if name == "sqr"
+    ));
+}
@@ -0,0 +1,148 @@
+//! Execution counts for finding where a program spends its time.
+
+use std::{collections::HashMap, fmt::Write};
+
+use super::{Assembler, Instruction, InstructionSet};
+
+#[derive(Debug, Default, Clone)]
+pub struct Profile {
+    /// executions per instruction index
+    pub per_pc: Vec<u64>,
+    /// executions per opcode index
+    pub per_opcode: Vec<u64>,
+    /// how often control went from one instruction index to another
+    pub transitions: HashMap<(usize, usize), u64>,
+    /// applications and replaced steps per idiom head
+    pub idioms: HashMap<usize, (u64, u64)>,
+}
+
+impl Profile {
+    pub(super) fn record(&mut self, pc: usize, opcode: usize, next: usize) {
+        if self.per_pc.len() <= pc {
+            self.per_pc.resize(pc + 1, 0);
+        }
+        self.per_pc[pc] += 1;
+        if self.per_opcode.len() <= opcode {
+            self.per_opcode.resize(opcode + 1, 0);
+        }
+        self.per_opcode[opcode] += 1;
+        *self.transitions.entry((pc, next)).or_default() += 1;
+    }
+
+    pub(super) fn record_idiom(&mut self, pc: usize, next: usize, steps: u64) {
+        let entry = self.idioms.entry(pc).or_default();
+        entry.0 += 1;
+        entry.1 += steps;
+        *self.transitions.entry((pc, next)).or_default() += 1;
+    }
+
+    /// total number of instructions executed, including replaced ones
+    pub fn steps(&self) -> u64 {
+        self.per_pc.iter().sum::<u64>() + self.idioms.values().map(|(_, s)| s).sum::<u64>()
+    }
+
+    /// The `top` most executed instructions, opcodes and transitions, with
+    /// each instruction shown as pseudo-code.
+    pub fn report(&self, instructions: &[Instruction], set: &InstructionSet, top: usize) -> String {
+        let total = self.steps().max(1);
+        let share = |count: u64| 100.0 * count as f64 / total as f64;
+        let code = |pc: usize| {
+            instructions
+                .get(pc)
+                .map_or(String::new(), |i| i.pseudo(set).trim_end().to_string())
+        };
+        let mut out = String::new();
+
+        writeln!(out, "hot spots ({} steps):", self.steps()).unwrap();
+        let mut pcs = self.per_pc.iter().copied().enumerate().collect::<Vec<_>>();
+        pcs.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for (pc, count) in pcs.into_iter().take(top).filter(|&(_, c)| c > 0) {
+            writeln!(
+                out,
+                "{:>4} {:>12} {:>6.2}%  {}",
+                pc,
+                count,
+                share(count),
+                code(pc)
+            )
+            .unwrap();
+        }
+
+        if !self.idioms.is_empty() {
+            writeln!(out, "idioms:").unwrap();
+            let mut idioms = self.idioms.iter().collect::<Vec<_>>();
+            idioms.sort_by(|a, b| b.1 .1.cmp(&a.1 .1).then(a.0.cmp(b.0)));
+            for (pc, (applied, steps)) in idioms {
+                writeln!(
+                    out,
+                    "{:>4} {:>12} {:>6.2}%  applied {} times at `{}`",
+                    pc,
+                    steps,
+                    share(*steps),
+                    applied,
+                    code(*pc)
+                )
+                .unwrap();
+            }
+        }
+
+        writeln!(out, "opcodes:").unwrap();
+        let mut opcodes = self
+            .per_opcode
+            .iter()
+            .copied()
+            .enumerate()
+            .collect::<Vec<_>>();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for (opcode, count) in opcodes.into_iter().take(top).filter(|&(_, c)| c > 0) {
+            writeln!(
+                out,
+                "{:>4} {:>12} {:>6.2}%",
+                set.get(opcode)
+                    .map_or_else(|| format!("op{}", opcode), |op| op.name().to_string()),
+                count,
+                share(count)
+            )
+            .unwrap();
+        }
+
+        writeln!(out, "transitions:").unwrap();
+        let mut transitions = self.transitions.iter().collect::<Vec<_>>();
+        transitions.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for ((from, to), count) in transitions.into_iter().take(top) {
+            writeln!(out, "{:>4} -> {:<4} {:>12}", from, to, count).unwrap();
+        }
+
+        out
+    }
+}
+
+impl Assembler {
+    /// Start counting executions from now on.
+    pub fn enable_profiling(&mut self) {
+        self.profile = Some(Profile::default());
+    }
+
+    /// Hot-spot report of everything executed since profiling was enabled.
+    pub fn profile_report(&self, top: usize) -> Option<String> {
+        self.profile
+            .as_ref()
+            .map(|profile| profile.report(&self.instructions, &self.set, top))
+    }
+}
+
+#[test]
+fn profile_counts_every_step() {
+    let mut assembler = Assembler::load(&crate::load(21)).unwrap();
+    assembler.enable_profiling();
+    assembler.exec(5000);
+
+    let profile = assembler.profile.as_ref().unwrap();
+    assert_eq!(profile.steps(), 5000);
+    assert_eq!(
+        profile.per_opcode.iter().sum::<u64>(),
+        profile.per_pc.iter().sum::<u64>()
+    );
+    assert!(profile.idioms.contains_key(&18));
+    assert!(assembler.profile_report(10).unwrap().contains("R1 += 1"));
+}
@@ -0,0 +1,867 @@
+//! Symbolic execution of device programs.
+//!
+//! Some registers start out unknown and every register holds an expression
+//! over those initial values. Constant parts are folded as soon as they are
+//! computed, so a program that only looks at an unknown now and then runs
+//! almost as it would concretely. Whenever the next instruction depends on
+//! an unknown the execution forks, and every path reports what it assumed
+//! about the initial registers and how it ended.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fmt::{self, Display},
+    rc::Rc,
+};
+
+use super::{
+    idiom::{divisors, Operand},
+    modes, Assembler, Idiom, Instruction, InstructionSet, Mode, REV,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinOp {
+    Add,
+    Mul,
+    And,
+    Or,
+    Gt,
+    Eq,
+}
+
+impl BinOp {
+    fn eval(self, x: usize, y: usize) -> usize {
+        match self {
+            BinOp::Add => x.wrapping_add(y),
+            BinOp::Mul => x.wrapping_mul(y),
+            BinOp::And => x & y,
+            BinOp::Or => x | y,
+            BinOp::Gt => (x > y) as usize,
+            BinOp::Eq => (x == y) as usize,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            BinOp::Add => "+",
+            BinOp::Mul => "*",
+            BinOp::And => "&",
+            BinOp::Or => "|",
+            BinOp::Gt => ">",
+            BinOp::Eq => "==",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Expr {
+    Const(usize),
+    /// the initial value of a register
+    Init(usize),
+    Bin(BinOp, Rc<Expr>, Rc<Expr>),
+    /// the effect of a summarised loop
+    Call(&'static str, Vec<Expr>),
+}
+
+impl Expr {
+    pub fn value(&self) -> Option<usize> {
+        match self {
+            Expr::Const(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// `a op b`, folded as far as possible
+    pub fn bin(op: BinOp, a: Expr, b: Expr) -> Expr {
+        use Expr::{Bin, Const};
+
+        match (op, &a, &b) {
+            (_, Const(x), Const(y)) => Const(op.eval(*x, *y)),
+            // keep constants on the right
+            (BinOp::Add | BinOp::Mul | BinOp::And | BinOp::Or | BinOp::Eq, Const(_), _) => {
+                Expr::bin(op, b, a)
+            }
+            (BinOp::Add | BinOp::Or, _, Const(0)) | (BinOp::Mul, _, Const(1)) => a,
+            (BinOp::Mul | BinOp::And, _, Const(0)) => Const(0),
+            (BinOp::Add, Bin(BinOp::Add, x, y), Const(k)) => match **y {
+                Const(j) => Expr::bin(op, (**x).clone(), Const(j.wrapping_add(*k))),
+                _ => Bin(op, Rc::new(a), Rc::new(b)),
+            },
+            _ => Bin(op, Rc::new(a), Rc::new(b)),
+        }
+    }
+
+    /// a summarised loop, evaluated if all arguments are known
+    pub fn call(name: &'static str, args: Vec<Expr>) -> Expr {
+        let values = args.iter().map(Expr::value).collect::<Option<Vec<_>>>();
+        let value = match (name, values.as_deref()) {
+            ("max", Some(&[x, y])) => Some(x.max(y)),
+            ("div", Some(&[x, y])) if y != 0 => Some(x / y),
+            ("divisor_sum", Some(&[n, from])) => {
+                Some(divisors(n).into_iter().filter(|&d| d >= from).sum())
+            }
+            _ => None,
+        };
+        match value {
+            Some(value) => Expr::Const(value),
+            None => Expr::Call(name, args),
+        }
+    }
+
+    /// replace every occurrence of `from` by `to`
+    pub fn substitute(&self, from: &Expr, to: &Expr) -> Expr {
+        if self == from {
+            return to.clone();
+        }
+        match self {
+            Expr::Const(_) | Expr::Init(_) => self.clone(),
+            Expr::Bin(op, a, b) => Expr::bin(*op, a.substitute(from, to), b.substitute(from, to)),
+            Expr::Call(name, args) => Expr::call(
+                name,
+                args.iter().map(|arg| arg.substitute(from, to)).collect(),
+            ),
+        }
+    }
+
+    /// rebuild through the folding constructors
+    pub fn fold(&self) -> Expr {
+        match self {
+            Expr::Const(_) | Expr::Init(_) => self.clone(),
+            Expr::Bin(op, a, b) => Expr::bin(*op, a.fold(), b.fold()),
+            Expr::Call(name, args) => Expr::call(name, args.iter().map(Expr::fold).collect()),
+        }
+    }
+
+    /// the innermost comparison, if any
+    fn comparison(&self) -> Option<&Expr> {
+        match self {
+            Expr::Bin(op, a, b) => a
+                .comparison()
+                .or_else(|| b.comparison())
+                .or_else(|| matches!(op, BinOp::Gt | BinOp::Eq).then(|| self)),
+            Expr::Call(_, args) => args.iter().find_map(Expr::comparison),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Const(v) => write!(f, "{}", v),
+            Expr::Init(r) => write!(f, "R{}", r),
+            Expr::Bin(op, a, b) => write!(f, "({} {} {})", a, op.symbol(), b),
+            Expr::Call(name, args) => {
+                let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+                write!(f, "{}({})", name, args.join(", "))
+            }
+        }
+    }
+}
+
+/// What a path knows about the initial value of an unknown register.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Range {
+    pub lo: usize,
+    pub hi: usize,
+    pub excluded: BTreeSet<usize>,
+}
+
+impl Default for Range {
+    fn default() -> Self {
+        Range {
+            lo: 0,
+            hi: usize::MAX,
+            excluded: BTreeSet::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Halt {
+        regs: Vec<Expr>,
+    },
+    /// the registers repeat, so only halts that were already found remain
+    Cycle,
+    /// the next instruction depends on the unknowns in a way that is not
+    /// understood, or an added opcode reads an unknown
+    Unsupported {
+        pc: Expr,
+    },
+    /// the step limit ran out
+    Limit,
+}
+
+#[derive(Debug, Clone)]
+pub struct Path {
+    /// initial values this path requires
+    pub fixed: BTreeMap<usize, usize>,
+    /// what this path requires of the initial values that stay unknown
+    pub ranges: BTreeMap<usize, Range>,
+    /// any other comparisons and the result this path requires
+    pub assumptions: Vec<(Expr, bool)>,
+    /// number of executed instructions, unless a loop was summarised over
+    /// unknown values
+    pub steps: Option<u64>,
+    /// loop head, applications and the last effect of each summarised loop
+    pub summaries: Vec<(usize, u64, String)>,
+    pub outcome: Outcome,
+}
+
+impl Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut conditions = vec![];
+        for (r, v) in self.fixed.iter() {
+            conditions.push(format!("R{} == {}", r, v));
+        }
+        for (r, range) in self.ranges.iter() {
+            if range.lo > 0 {
+                conditions.push(format!("R{} >= {}", r, range.lo));
+            }
+            if range.hi < usize::MAX {
+                conditions.push(format!("R{} <= {}", r, range.hi));
+            }
+            match range.excluded.len() {
+                0 => {}
+                n if n <= 5 => {
+                    for v in range.excluded.iter() {
+                        conditions.push(format!("R{} != {}", r, v));
+                    }
+                }
+                n => conditions.push(format!("R{} is none of {} values", r, n)),
+            }
+        }
+        for (cmp, holds) in self.assumptions.iter() {
+            conditions.push(if *holds {
+                cmp.to_string()
+            } else {
+                format!("!{}", cmp)
+            });
+        }
+        if conditions.is_empty() {
+            conditions.push("always".to_string());
+        }
+        writeln!(f, "when {}", conditions.join(" and "))?;
+
+        let steps = match self.steps {
+            Some(steps) => format!("after {} steps", steps),
+            None => "after an unknown number of steps".to_string(),
+        };
+        match &self.outcome {
+            Outcome::Halt { regs } => {
+                let regs = regs
+                    .iter()
+                    .enumerate()
+                    .map(|(r, e)| format!("R{} = {}", r, e))
+                    .collect::<Vec<_>>();
+                writeln!(f, "    halts {} with {}", steps, regs.join(", "))?;
+            }
+            Outcome::Cycle => writeln!(f, "    runs forever, repeating {}", steps)?,
+            Outcome::Unsupported { pc } => writeln!(f, "    jumps to {} {}", pc, steps)?,
+            Outcome::Limit => writeln!(f, "    is still running {}", steps)?,
+        }
+        for (pc, times, summary) in self.summaries.iter() {
+            writeln!(
+                f,
+                "    loop at {} ran {} times, last as {}",
+                pc, times, summary
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Assembler {
+    /// Explore the program with the registers in `unknown` left symbolic and
+    /// the others at their current value, executing at most `limit`
+    /// instructions over all paths.
+    ///
+    /// Paths are reported in the order they end, which for a path that
+    /// keeps running while others split off and halt is the order of their
+    /// step counts.
+    pub fn explore(&self, unknown: &[usize], limit: u64) -> Vec<Path> {
+        let mut regs: [Expr; 6] = Default::default();
+        for (r, reg) in regs.iter_mut().enumerate() {
+            *reg = if unknown.contains(&r) {
+                Expr::Init(r)
+            } else {
+                Expr::Const(self.regs[r])
+            };
+        }
+        let state = State {
+            pc: regs[self.ip].clone(),
+            regs,
+            steps: Some(0),
+            known: Rc::default(),
+            fixed: BTreeMap::new(),
+            assumptions: None,
+            summaries: Rc::default(),
+            decisions: 0,
+        };
+
+        let mut explorer = Explorer {
+            assembler: self,
+            limit,
+            paths: vec![],
+            pending: vec![],
+            trail: vec![],
+            seen: HashSet::new(),
+        };
+        explorer.run(state);
+        explorer.paths
+    }
+}
+
+impl Default for Expr {
+    fn default() -> Self {
+        Expr::Const(0)
+    }
+}
+
+/// a persistent list, so that forked paths share what they assumed before
+struct Node {
+    item: (Expr, bool),
+    next: Option<Rc<Node>>,
+}
+
+#[derive(Clone)]
+struct State {
+    /// index of the next instruction
+    pc: Expr,
+    regs: [Expr; 6],
+    steps: Option<u64>,
+    known: Rc<BTreeMap<usize, Range>>,
+    fixed: BTreeMap<usize, usize>,
+    assumptions: Option<Rc<Node>>,
+    summaries: Rc<BTreeMap<usize, (u64, String)>>,
+    /// decisions taken so far, which are the first entries of
+    /// `Explorer::trail` while this state runs
+    decisions: usize,
+}
+
+enum Choice {
+    Cmp(Expr, bool),
+    Value(usize, usize),
+    /// the unknown is at least this large, which leaves the program
+    Leave(usize, usize),
+}
+
+struct Explorer<'a> {
+    assembler: &'a Assembler,
+    limit: u64,
+    paths: Vec<Path>,
+    /// forks still to run, the next one last, and whether they run at all
+    /// or just halt
+    pending: Vec<(State, bool)>,
+    /// the registers at each decision of the path being run, also kept in
+    /// `seen`, to notice cycles
+    trail: Vec<(Expr, [Expr; 6])>,
+    seen: HashSet<(Expr, [Expr; 6])>,
+}
+
+impl<'a> Explorer<'a> {
+    /// Run `state` and every fork of it, depth first.
+    fn run(&mut self, state: State) {
+        self.pending.push((state, true));
+        while let Some((state, runs)) = self.pending.pop() {
+            if runs {
+                self.follow(state);
+            } else {
+                self.halt(&state);
+            }
+        }
+    }
+
+    /// Run `state` until it ends or forks.
+    fn follow(&mut self, mut state: State) {
+        let ip = self.assembler.ip;
+        loop {
+            let pc = match state.pc.value() {
+                Some(pc) => pc,
+                None => {
+                    // the trail still ends in decisions of the paths run
+                    // before, which this one did not take
+                    for key in self.trail.drain(state.decisions..) {
+                        self.seen.remove(&key);
+                    }
+                    let key = (state.pc.clone(), state.regs.clone());
+                    if !self.seen.insert(key.clone()) {
+                        return self.finish(&state, Outcome::Cycle);
+                    }
+                    self.trail.push(key);
+                    state.decisions += 1;
+                    let mut choices = match self.choices(&state) {
+                        Some(choices) => choices,
+                        None => {
+                            let pc = state.pc.clone();
+                            return self.finish(&state, Outcome::Unsupported { pc });
+                        }
+                    };
+
+                    // the last choice takes `state` itself rather than a copy
+                    let last = match choices.pop() {
+                        Some(last) => last,
+                        None => return,
+                    };
+                    let mut forks = choices
+                        .into_iter()
+                        .map(|choice| {
+                            let mut child = state.clone();
+                            let runs = child.assume(choice);
+                            (child, runs)
+                        })
+                        .collect::<Vec<_>>();
+                    let runs = state.assume(last);
+                    forks.push((state, runs));
+                    // each fork runs to the end before the next one starts
+                    for (fork, runs) in forks.into_iter().rev() {
+                        if let Some(runs) = runs {
+                            self.pending.push((fork, runs));
+                        }
+                    }
+                    return;
+                }
+            };
+
+            let i = match self.assembler.instructions.get(pc) {
+                Some(Instruction::OPCode(i)) => i,
+                _ => return self.halt(&state),
+            };
+            if self.limit == 0 {
+                return self.finish(&state, Outcome::Limit);
+            }
+            self.limit -= 1;
+
+            state.regs[ip] = Expr::Const(pc);
+            if let Some(Some(idiom)) = self.assembler.idioms.get(pc) {
+                if state.summarise(idiom, ip, pc) {
+                    continue;
+                }
+            }
+
+            state.regs[i[3]] = match evaluate(i, &state.regs, &self.assembler.set) {
+                Effect::Write(value) => value,
+                Effect::Stop => {
+                    state.pc = Expr::Const(pc);
+                    return self.halt(&state);
+                }
+                Effect::Unknown => {
+                    let pc = Expr::Const(pc);
+                    return self.finish(&state, Outcome::Unsupported { pc });
+                }
+            };
+            state.pc = Expr::bin(BinOp::Add, state.regs[ip].clone(), Expr::Const(1));
+            state.steps = state.steps.map(|steps| steps + 1);
+        }
+    }
+
+    /// the ways to continue from a state whose next instruction is unknown
+    fn choices(&self, state: &State) -> Option<Vec<Choice>> {
+        if let Some(cmp) = state.pc.comparison() {
+            return Some(match state.decide(cmp) {
+                Some(holds) => vec![Choice::Cmp(cmp.clone(), holds)],
+                None => vec![
+                    Choice::Cmp(cmp.clone(), true),
+                    Choice::Cmp(cmp.clone(), false),
+                ],
+            });
+        }
+
+        // a jump by an unknown offset
+        let (r, k) = match &state.pc {
+            Expr::Init(r) => (*r, 0),
+            Expr::Bin(BinOp::Add, a, b) => match (&**a, &**b) {
+                (Expr::Init(r), Expr::Const(k)) => (*r, *k),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        let len = self.assembler.instructions.len();
+        let default = Range::default();
+        let range = state.known.get(&r).unwrap_or(&default);
+
+        let mut choices = vec![];
+        let mut v = range.lo;
+        while v <= range.hi && k + v < len {
+            if !range.excluded.contains(&v) {
+                choices.push(Choice::Value(r, v));
+            }
+            v += 1;
+        }
+        if k.saturating_add(range.hi) >= len {
+            choices.push(Choice::Leave(r, len.saturating_sub(k).max(range.lo)));
+        }
+        Some(choices)
+    }
+
+    fn halt(&mut self, state: &State) {
+        // the ip register ends past the program, as it does when interpreted
+        let mut regs = state.regs.to_vec();
+        regs[self.assembler.ip] = state.pc.clone();
+        self.finish(state, Outcome::Halt { regs });
+    }
+
+    fn finish(&mut self, state: &State, outcome: Outcome) {
+        let mut assumptions = vec![];
+        let mut node = &state.assumptions;
+        while let Some(n) = node {
+            assumptions.push(n.item.clone());
+            node = &n.next;
+        }
+        assumptions.reverse();
+
+        self.paths.push(Path {
+            fixed: state.fixed.clone(),
+            ranges: state
+                .known
+                .iter()
+                .filter(|(r, _)| !state.fixed.contains_key(r))
+                .map(|(r, range)| (*r, range.clone()))
+                .collect(),
+            assumptions,
+            steps: state.steps,
+            summaries: state
+                .summaries
+                .iter()
+                .map(|(pc, (times, summary))| (*pc, *times, summary.clone()))
+                .collect(),
+            outcome,
+        });
+    }
+}
+
+impl State {
+    /// Take a choice. Returns false if it means leaving the program, or
+    /// `None` if no initial values allow it and the path is infeasible.
+    fn assume(&mut self, choice: Choice) -> Option<bool> {
+        match choice {
+            Choice::Cmp(cmp, holds) => {
+                if !self.learn(&cmp, holds) {
+                    return None;
+                }
+                self.replace(&cmp, &Expr::Const(holds as usize));
+            }
+            Choice::Value(r, v) => self.fix(r, v),
+            Choice::Leave(r, lo) => {
+                let range = Rc::make_mut(&mut self.known).entry(r).or_default();
+                range.lo = range.lo.max(lo);
+                return Some(false);
+            }
+        }
+        Some(true)
+    }
+
+    fn fix(&mut self, r: usize, v: usize) {
+        self.fixed.insert(r, v);
+        self.replace(&Expr::Init(r), &Expr::Const(v));
+    }
+
+    fn replace(&mut self, from: &Expr, to: &Expr) {
+        for reg in self.regs.iter_mut() {
+            *reg = reg.substitute(from, to);
+        }
+        self.pc = self.pc.substitute(from, to);
+    }
+
+    /// Remember the result of a comparison. Returns false if no initial
+    /// value can give it.
+    fn learn(&mut self, cmp: &Expr, holds: bool) -> bool {
+        use Expr::{Bin, Const, Init};
+
+        if let Bin(op, a, b) = cmp {
+            match (op, &**a, &**b) {
+                (BinOp::Eq, Init(r), Const(v)) if holds => {
+                    let range = self.range(*r);
+                    if *v < range.lo || *v > range.hi || range.excluded.contains(v) {
+                        return false;
+                    }
+                    self.fix(*r, *v);
+                    return true;
+                }
+                (BinOp::Eq, Init(r), Const(v)) => {
+                    self.range(*r).excluded.insert(*v);
+                    return true;
+                }
+                (BinOp::Gt, Init(r), Const(v)) => {
+                    let range = self.range(*r);
+                    if holds {
+                        match v.checked_add(1) {
+                            Some(lo) => range.lo = range.lo.max(lo),
+                            None => return false,
+                        }
+                    } else {
+                        range.hi = range.hi.min(*v);
+                    }
+                    return self.settle(*r);
+                }
+                (BinOp::Gt, Const(v), Init(r)) => {
+                    let range = self.range(*r);
+                    if holds {
+                        match v.checked_sub(1) {
+                            Some(hi) => range.hi = range.hi.min(hi),
+                            None => return false,
+                        }
+                    } else {
+                        range.lo = range.lo.max(*v);
+                    }
+                    return self.settle(*r);
+                }
+                _ => {}
+            }
+        }
+
+        self.assumptions = Some(Rc::new(Node {
+            item: (cmp.clone(), holds),
+            next: self.assumptions.take(),
+        }));
+        true
+    }
+
+    fn range(&mut self, r: usize) -> &mut Range {
+        Rc::make_mut(&mut self.known).entry(r).or_default()
+    }
+
+    /// Fix an unknown whose range has narrowed to a single value. Returns
+    /// false if the range is empty.
+    fn settle(&mut self, r: usize) -> bool {
+        let range = &self.known[&r];
+        if range.lo > range.hi {
+            return false;
+        }
+        if range.lo == range.hi {
+            self.fix(r, range.lo);
+        }
+        true
+    }
+
+    /// the result of a comparison, if what the path knows decides it
+    fn decide(&self, cmp: &Expr) -> Option<bool> {
+        use Expr::{Bin, Const, Init};
+
+        if let Bin(op, a, b) = cmp {
+            let known = |r: &usize| self.known.get(r);
+            match (op, &**a, &**b) {
+                (BinOp::Eq, Init(r), Const(v)) => {
+                    if let Some(range) = known(r) {
+                        if *v < range.lo || *v > range.hi || range.excluded.contains(v) {
+                            return Some(false);
+                        }
+                    }
+                }
+                (BinOp::Gt, Init(r), Const(v)) => {
+                    if let Some(range) = known(r) {
+                        if range.lo > *v {
+                            return Some(true);
+                        } else if range.hi <= *v {
+                            return Some(false);
+                        }
+                    }
+                }
+                (BinOp::Gt, Const(v), Init(r)) => {
+                    if let Some(range) = known(r) {
+                        if range.hi < *v {
+                            return Some(true);
+                        } else if range.lo >= *v {
+                            return Some(false);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut node = &self.assumptions;
+        while let Some(n) = node {
+            if n.item.0 == *cmp {
+                return Some(n.item.1);
+            }
+            node = &n.next;
+        }
+        None
+    }
+
+    /// Run the loop of `idiom` in one go. Returns false if it has to be
+    /// executed instruction by instruction.
+    fn summarise(&mut self, idiom: &Idiom, ip: usize, pc: usize) -> bool {
+        let summary = summary(idiom, &self.regs);
+        let reads = idiom.reads();
+        if reads.iter().all(|&r| self.regs[r].value().is_some()) {
+            let mut regs = [0; 6];
+            for &r in reads.iter() {
+                regs[r] = self.regs[r].value().unwrap();
+            }
+            regs[ip] = pc;
+            let (out, steps) = match idiom.apply(ip, &regs) {
+                Some(result) => result,
+                None => return false,
+            };
+            for &w in idiom.writes().iter() {
+                self.regs[w] = Expr::Const(out[w]);
+            }
+            self.pc = Expr::Const(out[ip]);
+            self.steps = self.steps.map(|s| s + steps);
+        } else {
+            let (writes, exit) = match &summary {
+                Some(summary) => summary,
+                None => return false,
+            };
+            for (w, expr) in writes.iter() {
+                self.regs[*w] = expr.fold();
+            }
+            self.pc = Expr::Const(*exit);
+            self.steps = None;
+        }
+
+        let text = match summary {
+            Some((writes, _)) => writes
+                .iter()
+                .map(|(w, expr)| format!("R{} = {}", w, expr))
+                .collect::<Vec<_>>()
+                .join(", "),
+            None => format!("{:?}", idiom),
+        };
+        let entry = Rc::make_mut(&mut self.summaries)
+            .entry(pc)
+            .or_insert((0, String::new()));
+        entry.0 += 1;
+        entry.1 = text;
+        true
+    }
+}
+
+/// The effect of an idiom on the registers it writes, before folding, and
+/// where it exits, for the idioms that have a closed form.
+fn summary(idiom: &Idiom, regs: &[Expr; 6]) -> Option<(Vec<(usize, Expr)>, usize)> {
+    use Expr::{Bin, Call, Const};
+
+    let raw = |op, a: &Expr, b: Expr| Bin(op, Rc::new(a.clone()), Rc::new(b));
+    match *idiom {
+        Idiom::DivisorSum {
+            a,
+            b,
+            n,
+            t,
+            acc,
+            exit,
+        } if regs[a].value().is_some_and(|v| v > 0) => {
+            let (x, n) = (regs[a].clone(), regs[n].clone());
+            let sum = Call("divisor_sum", vec![n.clone(), x.clone()]);
+            Some((
+                vec![
+                    (acc, raw(BinOp::Add, &regs[acc], sum)),
+                    (
+                        a,
+                        raw(BinOp::Add, &Call("max", vec![x, n.clone()]), Const(1)),
+                    ),
+                    (
+                        b,
+                        raw(BinOp::Add, &Call("max", vec![n, Const(1)]), Const(1)),
+                    ),
+                    (t, Const(1)),
+                ],
+                exit,
+            ))
+        }
+        Idiom::Division { q, d, n, t, exit } => {
+            let d = match d {
+                Operand::Reg(r) => regs[r].clone(),
+                Operand::Imm(v) => Const(v),
+            };
+            if d.value().is_none_or(|v| v == 0) {
+                return None;
+            }
+            let div = Call("div", vec![regs[n].clone(), d]);
+            Some((
+                vec![(q, Call("max", vec![regs[q].clone(), div])), (t, Const(1))],
+                exit,
+            ))
+        }
+        _ => None,
+    }
+}
+
+enum Effect {
+    Write(Expr),
+    Stop,
+    /// an added opcode on unknown operands
+    Unknown,
+}
+
+/// what an instruction does
+fn evaluate(i: &[usize; 4], regs: &[Expr; 6], set: &InstructionSet) -> Effect {
+    let operand = |mode: Mode, v: usize| match mode {
+        Mode::Reg => regs[v].clone(),
+        Mode::Imm => Expr::Const(v),
+        Mode::Ignored => Expr::Const(0),
+    };
+    let name = match REV.get(&i[0]) {
+        Some(name) => name.as_str(),
+        None => {
+            let op = set.get(i[0]).unwrap();
+            let (ma, mb) = op.modes();
+            let values = (operand(ma, i[1]).value(), operand(mb, i[2]).value());
+            return match values {
+                (Some(a), Some(b)) => match op.eval(a, b) {
+                    Some(value) => Effect::Write(Expr::Const(value)),
+                    None => Effect::Stop,
+                },
+                _ => Effect::Unknown,
+            };
+        }
+    };
+    let (ma, mb) = modes(name);
+    let (a, b) = (operand(ma, i[1]), operand(mb, i[2]));
+
+    let op = match &name[..2] {
+        "ad" => BinOp::Add,
+        "mu" => BinOp::Mul,
+        "ba" => BinOp::And,
+        "bo" => BinOp::Or,
+        "se" => return Effect::Write(a),
+        "gt" => BinOp::Gt,
+        "eq" => BinOp::Eq,
+        _ => unreachable!(),
+    };
+    Effect::Write(Expr::bin(op, a, b))
+}
+
+#[test]
+fn symbolic_paths() {
+    let halts = |paths: &[Path]| {
+        paths
+            .iter()
+            .filter_map(|path| match &path.outcome {
+                Outcome::Halt { regs } => Some((path.fixed.get(&0).copied(), regs[0].clone())),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let d19 = Assembler::load(&crate::load(19)).unwrap();
+    let paths = d19.explore(&[0], 10_000_000);
+    let found = halts(&paths);
+    assert!(found.contains(&(Some(0), Expr::Const(2160))));
+    assert!(found.contains(&(Some(1), Expr::Const(25945920))));
+
+    let d21 = Assembler::load(&crate::load(21)).unwrap();
+    let paths = d21.explore(&[0], 10_000_000);
+    assert_eq!(paths.last().unwrap().outcome, Outcome::Cycle);
+    let found = halts(&paths);
+    assert_eq!(found.last().unwrap().0, Some(15400966));
+
+    // no R0 is below 0 or above the largest word, so only one branch of
+    // each comparison is feasible
+    for source in [
+        "#ip 5\ngtir 0 0 1\naddr 1 5 5\nseti 1 0 2\nseti 9 0 5",
+        "#ip 5\ngtri 0 18446744073709551615 1\naddr 1 5 5\nseti 1 0 2\nseti 9 0 5",
+    ] {
+        let paths = Assembler::load(source).unwrap().explore(&[0], 100);
+        assert_eq!(halts(&paths), [(None, Expr::Init(0))], "{}", source);
+    }
+
+    // a fork every few steps, far more than fit on the stack as calls
+    let source = "#ip 5\nseti 0 0 4\naddi 2 1 2\ngtrr 0 2 1\naddr 1 5 5\nseti 99 0 5\nseti 0 0 5";
+    let paths = Assembler::load(source).unwrap().explore(&[0], 40_000);
+    assert_eq!(paths.len(), 10_001);
+    assert_eq!(paths.last().unwrap().outcome, Outcome::Limit);
+}
@@ -0,0 +1,105 @@
+//! Translation of a device program into Rust source.
+
+use std::fmt::Write;
+
+use super::{Assembler, Instruction, Mode, Opcode};
+use crate::{AoCError, Result};
+
+impl Assembler {
+    /// A standalone Rust function called `name` that takes the initial
+    /// registers, runs the program until it halts and returns the final
+    /// registers.
+    ///
+    /// The registers are locals and the program is a `loop` over a `match`
+    /// on the ip register, with reads of the ip register replaced by the
+    /// index of the instruction. Fails if the program uses an opcode
+    /// without a Rust form.
+    pub fn transpile(&self, name: &str) -> Result<String> {
+        let ip = self.ip;
+        let mut written = vec![ip];
+        let mut arms = String::new();
+        for (pc, instruction) in self.instructions.iter().enumerate() {
+            let i = match instruction {
+                Instruction::OPCode(i) => i,
+                Instruction::IP(_) => unreachable!(),
+            };
+            written.push(i[3]);
+            let op = self.set.get(i[0]).unwrap();
+            writeln!(
+                arms,
+                "            {} => {}, // {}",
+                pc,
+                statement(op, i, ip, pc)?,
+                instruction.to_string().trim_end()
+            )
+            .unwrap();
+        }
+
+        let locals = (0..6)
+            .map(|r| {
+                if written.contains(&r) {
+                    format!("mut r{}", r)
+                } else {
+                    format!("r{}", r)
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut out = String::new();
+        writeln!(out, "pub fn {}(regs: [usize; 6]) -> [usize; 6] {{", name).unwrap();
+        writeln!(out, "    let [{}] = regs;", locals.join(", ")).unwrap();
+        writeln!(out, "    loop {{").unwrap();
+        writeln!(out, "        match r{} {{", ip).unwrap();
+        out.push_str(&arms);
+        writeln!(out, "            _ => break,").unwrap();
+        writeln!(out, "        }}").unwrap();
+        writeln!(out, "        r{} += 1;", ip).unwrap();
+        writeln!(out, "    }}").unwrap();
+        writeln!(out, "    [r0, r1, r2, r3, r4, r5]").unwrap();
+        writeln!(out, "}}").unwrap();
+        Ok(out)
+    }
+}
+
+/// the effect of an instruction
+fn statement(op: &dyn Opcode, i: &[usize; 4], ip: usize, pc: usize) -> Result<String> {
+    let operand = |mode: Mode, v: usize| match mode {
+        Mode::Reg if v == ip => pc.to_string(),
+        Mode::Reg => format!("r{}", v),
+        Mode::Imm => v.to_string(),
+        Mode::Ignored => String::new(),
+    };
+    let (ma, mb) = op.modes();
+    let (a, b) = (operand(ma, i[1]), operand(mb, i[2]));
+
+    op.rust(&a, &b, &format!("r{}", i[3]))
+        .ok_or_else(|| AoCError::NoRustForm(op.name().to_string()))
+}
+
+#[test]
+fn transpiled_matches_interpretation() {
+    use std::{env, fs, process::Command};
+
+    let mut assembler = Assembler::load(&crate::load(19)).unwrap();
+    let mut source = assembler.transpile("run").unwrap();
+    source.push_str("\nfn main() {\n    println!(\"{:?}\", run([0; 6]));\n}\n");
+
+    let dir = env::temp_dir().join(format!("aoc2018-transpile-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (src, bin) = (dir.join("d19.rs"), dir.join("d19"));
+    fs::write(&src, &source).unwrap();
+    let status = Command::new("rustc")
+        .args(["--edition", "2021", "-O", "-o"])
+        .args([&bin, &src])
+        .status()
+        .unwrap();
+    assert!(status.success(), "{}", source);
+    let output = Command::new(&bin).output().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assembler.interpret(u64::MAX);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().trim(),
+        format!("{:?}", assembler.regs)
+    );
+}
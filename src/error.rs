@@ -1,6 +1,8 @@
 use failure::Fail;
 use std::{io, num::ParseIntError};
 
+use crate::device::VmError;
+
 #[derive(Fail, Debug)]
 pub enum AoCError {
     #[fail(display = "{}", _0)]
@@ -13,6 +15,18 @@ pub enum AoCError {
     IO(#[cause] io::Error),
     #[fail(display = "{}", _0)]
     Log(#[cause] fern::InitError),
+    #[fail(display = "{}", _0)]
+    Vm(#[cause] VmError),
+    #[fail(display = "{}", _0)]
+    Json(#[cause] serde_json::Error),
+    #[fail(display = "line {}: {}", line, msg)]
+    Asm { line: usize, msg: String },
+    #[fail(display = "opcode `{}` is already defined", _0)]
+    DuplicateOpcode(String),
+    #[fail(display = "opcode `{}` has no Rust form", _0)]
+    NoRustForm(String),
+    #[fail(display = "event does not fit the battle: {}", _0)]
+    Replay(String),
     #[fail(display = "dirty input")]
     DirtyInput,
 }
@@ -45,3 +59,15 @@ impl From<fern::InitError> for AoCError {
         AoCError::Log(err)
     }
 }
+
+impl From<VmError> for AoCError {
+    fn from(err: VmError) -> Self {
+        AoCError::Vm(err)
+    }
+}
+
+impl From<serde_json::Error> for AoCError {
+    fn from(err: serde_json::Error) -> Self {
+        AoCError::Json(err)
+    }
+}
@@ -1,9 +1,17 @@
-use std::{env::current_dir, fs::File, io::Read};
+use std::{
+    env::current_dir,
+    fs::File,
+    io::{Read, Write},
+};
+
+use serde::Serialize;
 
 pub use error::AoCError;
 
 pub type Result<T> = std::result::Result<T, AoCError>;
 
+pub mod cave;
+pub mod device;
 mod error;
 
 /// load input from specified day.
@@ -17,6 +25,20 @@ pub fn load(day: u64) -> String {
     buf
 }
 
+/// whether `name` was passed on the command line
+pub fn flag(name: &str) -> bool {
+    std::env::args().any(|arg| arg == name)
+}
+
+/// Write each item as JSON on a line of its own.
+pub fn write_json_lines<T: Serialize>(items: &[T], mut w: impl Write) -> Result<()> {
+    for item in items {
+        serde_json::to_writer(&mut w, item)?;
+        writeln!(w)?;
+    }
+    Ok(())
+}
+
 /// handy function to set up general-used logger with fern.
 pub fn setup_logger() -> Result<()> {
     fern::Dispatch::new()